@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::error::OspreyError;
+
+// the configuration file read from the working directory when --config isn't given
+pub const DEFAULT_CONFIG_FILE: &str = "osprey.toml";
+
+// Config holds the settings of an osprey.toml, every key is optional
+//   migrations_directory - a directory or a list of them, relative to the working directory
+//   tag - the tag commands use when none is given
+//   database - connection settings, used when the environment doesn't set them
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub migrations_directory: Option<Directories>,
+    pub migrations_table: Option<String>,
+    pub tag: Option<String>,
+    pub database: DatabaseConfig,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Directories {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Directories {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            Directories::One(directory) => vec![directory],
+            Directories::Many(directories) => directories,
+        }
+    }
+}
+
+// DatabaseConfig is the [database] table, the counterpart of DATABASE_URL and the POSTGRES_*
+// environment variables
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub name: Option<String>,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, OspreyError> {
+        let contents = fs::read_to_string(&path)?;
+        toml::from_str(&contents).map_err(|err| {
+            OspreyError::InvalidConfig(path.as_ref().display().to_string(), err.to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(
+            file.path(),
+            "migrations_directory = [\"users\", \"billing\"]\ntag = \"seed\"\n\n[database]\nhost = \"db\"\nport = 5433\nname = \"app\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            Config::load(file.path()).unwrap(),
+            Config {
+                migrations_directory: Some(Directories::Many(vec![
+                    "users".to_string(),
+                    "billing".to_string()
+                ])),
+                migrations_table: None,
+                tag: Some("seed".to_string()),
+                database: DatabaseConfig {
+                    host: Some("db".to_string()),
+                    port: Some(5433),
+                    name: Some("app".to_string()),
+                    ..DatabaseConfig::default()
+                },
+            }
+        );
+
+        fs::write(file.path(), "migrations_directory = \"db\"\n").unwrap();
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.migrations_directory.unwrap().into_vec(), vec!["db"]);
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        for contents in [
+            "migrations_tabel = \"x\"\n",
+            "[database]\nport = \"high\"\n",
+        ] {
+            fs::write(file.path(), contents).unwrap();
+            assert!(matches!(
+                Config::load(file.path()),
+                Err(OspreyError::InvalidConfig(_, _))
+            ));
+        }
+
+        assert!(matches!(
+            Config::load("/does/not/exist/osprey.toml"),
+            Err(OspreyError::Io(_))
+        ));
+    }
+}
@@ -1,12 +1,22 @@
 use std::fs;
-use std::io;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 
+use glob::Pattern;
+
 use crate::error::OspreyError;
 
+// Directory holds where migration files are read from
+//   path - the directory itself
+//   recursive - whether files in nested directories are included as well
+//   include - glob patterns a file's path must match one of, every file if empty
+//   exclude - glob patterns of files that are left out
 #[derive(Debug)]
 pub struct Directory {
     path: PathBuf,
+    recursive: bool,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
 }
 
 impl Directory {
@@ -18,26 +28,237 @@ impl Directory {
 
         Ok(Self {
             path: dir_path.to_path_buf(),
+            recursive: false,
+            include: vec![],
+            exclude: vec![],
         })
     }
 
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    pub fn include(mut self, patterns: &[String]) -> Result<Self, OspreyError> {
+        self.include = Self::parse_patterns(patterns)?;
+        Ok(self)
+    }
+
+    pub fn exclude(mut self, patterns: &[String]) -> Result<Self, OspreyError> {
+        self.exclude = Self::parse_patterns(patterns)?;
+        Ok(self)
+    }
+
+    fn parse_patterns(patterns: &[String]) -> Result<Vec<Pattern>, OspreyError> {
+        patterns
+            .iter()
+            .map(|p| Pattern::new(p).map_err(|_| OspreyError::InvalidPattern(p.clone())))
+            .collect()
+    }
+
+    // patterns are matched against the path relative to the directory, so `*_ignore.sql`
+    // matches at any depth while `2024/*` only matches inside that folder
+    fn is_selected(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.path).unwrap_or(path);
+        let included =
+            self.include.is_empty() || self.include.iter().any(|p| p.matches_path(relative));
+        included && !self.exclude.iter().any(|p| p.matches_path(relative))
+    }
+
+    // the files with the given extension, sorted by path so every machine sees the same order
     pub fn get_file_list(&self, extension: &str) -> Result<Vec<PathBuf>, OspreyError> {
         let mut list = vec![];
+        self.collect_files(&self.path, extension, &mut list)?;
 
-        let entries = fs::read_dir(&self.path)?
-            .map(|res| res.map(|e| e.path()))
-            .collect::<Result<Vec<PathBuf>, io::Error>>()?;
+        list.sort();
+        Ok(list)
+    }
+
+    // creates a new file in the directory, an existing file is never overwritten
+    pub fn create_file(&self, file_name: &str, contents: &str) -> Result<PathBuf, OspreyError> {
+        let path = self.path.join(file_name);
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::AlreadyExists => OspreyError::FileExists(path.display().to_string()),
+                _ => OspreyError::Io(err),
+            })?;
+        file.write_all(contents.as_bytes())?;
+        Ok(path)
+    }
+
+    // symlinked directories aren't followed so a link back up the tree can't loop forever
+    fn collect_files(
+        &self,
+        path: &Path,
+        extension: &str,
+        list: &mut Vec<PathBuf>,
+    ) -> Result<(), OspreyError> {
+        let entries = fs::read_dir(path)?.collect::<Result<Vec<fs::DirEntry>, io::Error>>()?;
 
         for entry in entries {
-            let file_extension = entry
+            let entry_path = entry.path();
+            if entry_path.is_dir() {
+                if self.recursive && entry.file_type()?.is_dir() {
+                    self.collect_files(&entry_path, extension, list)?;
+                }
+                continue;
+            }
+
+            let file_extension = entry_path
                 .extension()
                 .or_else(|| Some(std::ffi::OsStr::new("")))
                 .unwrap();
-            if !entry.is_dir() && file_extension == extension {
-                list.push(entry);
+            if file_extension == extension && self.is_selected(&entry_path) {
+                list.push(entry_path);
             }
         }
 
-        Ok(list)
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_list_sorted() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in &[
+            "010_c.sql",
+            "002_b.sql",
+            "notes.txt",
+            "001_a.sql",
+            "100_d.sql",
+        ] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        fs::create_dir(dir.path().join("000_dir.sql")).unwrap();
+
+        let directory = Directory::new(dir.path().to_str().unwrap()).unwrap();
+        let names: Vec<_> = directory
+            .get_file_list("sql")
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["001_a.sql", "002_b.sql", "010_c.sql", "100_d.sql"]
+        );
+    }
+
+    #[test]
+    fn test_recursive_file_list() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("2024").join("q1")).unwrap();
+        for name in &[
+            "001_a.sql",
+            "2024/002_b.sql",
+            "2024/q1/003_c.sql",
+            "2024/notes.txt",
+        ] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+
+        let names = |directory: Directory| -> Vec<String> {
+            directory
+                .get_file_list("sql")
+                .unwrap()
+                .iter()
+                .map(|p| {
+                    p.strip_prefix(dir.path())
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .replace('\\', "/")
+                })
+                .collect()
+        };
+
+        let directory = Directory::new(dir.path().to_str().unwrap()).unwrap();
+        assert_eq!(names(directory), vec!["001_a.sql"]);
+
+        let directory = Directory::new(dir.path().to_str().unwrap())
+            .unwrap()
+            .recursive(true);
+        assert_eq!(
+            names(directory),
+            vec!["001_a.sql", "2024/002_b.sql", "2024/q1/003_c.sql"]
+        );
+    }
+
+    #[test]
+    fn test_file_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("2024")).unwrap();
+        for name in &[
+            "001_a.sql",
+            "schema_dump.sql",
+            "002_b_ignore.sql",
+            "2024/003_c.sql",
+            "2024/004_d_ignore.sql",
+        ] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+
+        let names = |directory: Directory| -> Vec<String> {
+            directory
+                .get_file_list("sql")
+                .unwrap()
+                .iter()
+                .map(|p| p.file_name().unwrap().to_str().unwrap().to_string())
+                .collect()
+        };
+        let new_directory = || {
+            Directory::new(dir.path().to_str().unwrap())
+                .unwrap()
+                .recursive(true)
+        };
+
+        let directory = new_directory()
+            .exclude(&["*_ignore.sql".to_string(), "schema_dump.sql".to_string()])
+            .unwrap();
+        assert_eq!(names(directory), vec!["001_a.sql", "003_c.sql"]);
+
+        let directory = new_directory()
+            .include(&["2024/*".to_string()])
+            .unwrap()
+            .exclude(&["*_ignore.sql".to_string()])
+            .unwrap();
+        assert_eq!(names(directory), vec!["003_c.sql"]);
+
+        assert!(matches!(
+            new_directory().exclude(&["[".to_string()]),
+            Err(OspreyError::InvalidPattern(_))
+        ));
+    }
+
+    #[test]
+    fn test_create_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let directory = Directory::new(dir.path().to_str().unwrap()).unwrap();
+
+        let path = directory.create_file("001_a.sql", "SELECT 1;").unwrap();
+        assert_eq!(path, dir.path().join("001_a.sql"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "SELECT 1;");
+
+        assert!(matches!(
+            directory.create_file("001_a.sql", "SELECT 2;"),
+            Err(OspreyError::FileExists(_))
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "SELECT 1;");
+    }
+
+    #[test]
+    fn test_not_a_directory() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        assert!(matches!(
+            Directory::new(file.path().to_str().unwrap()),
+            Err(OspreyError::NotADirectory)
+        ));
     }
 }
@@ -1,12 +1,236 @@
 use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::error::OspreyError;
 
 pub struct Env {}
 
 impl Env {
+    pub fn get_value(key: &str) -> Option<String> {
+        env::var(key).ok()
+    }
+
     pub fn get_value_or_default(key: &str, default: &str) -> String {
         match env::var(key) {
             Ok(v) => v,
             Err(_) => default.to_string(),
         }
     }
+
+    pub fn get_required(key: &str) -> Result<String, OspreyError> {
+        Self::get_value(key).ok_or_else(|| OspreyError::MissingEnvValue(key.to_string()))
+    }
+
+    // sets the variables of a .env file that aren't set in the environment already, so the real
+    // environment always wins
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<(), OspreyError> {
+        let contents = fs::read_to_string(path)?;
+        for (key, value) in Self::parse(&contents) {
+            if env::var_os(&key).is_none() {
+                env::set_var(key, value);
+            }
+        }
+        Ok(())
+    }
+
+    // KEY=VALUE lines, optionally prefixed with `export` and with the value in matching quotes.
+    // blank lines, comments and lines without a = are skipped
+    fn parse(contents: &str) -> Vec<(String, String)> {
+        let mut variables = vec![];
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            if let Some((key, value)) = line.split_once('=') {
+                let value = value.trim();
+                let value = [('"', '"'), ('\'', '\'')]
+                    .iter()
+                    .find_map(|(open, close)| {
+                        value
+                            .strip_prefix(*open)
+                            .and_then(|v| v.strip_suffix(*close))
+                    })
+                    .unwrap_or(value);
+                variables.push((key.trim().to_string(), value.to_string()));
+            }
+        }
+        variables
+    }
+
+    // substitutes ${VAR} with the variable's value and ${VAR:-default} with the value, or the
+    // default when it's unset or empty. a variable without a default has to be set. anything
+    // else starting with $, like $1 parameters and $$ quoted bodies, is left alone
+    pub fn interpolate(text: &str) -> Result<String, OspreyError> {
+        let mut interpolated = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find("${") {
+            interpolated.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let expression = match after.find('}') {
+                Some(end) => &after[..end],
+                None => {
+                    rest = &rest[start..];
+                    break;
+                }
+            };
+
+            let (key, default) = match expression.split_once(":-") {
+                Some((key, default)) => (key, Some(default)),
+                None => (expression, None),
+            };
+            if !Self::is_variable_name(key) {
+                interpolated.push_str("${");
+                rest = after;
+                continue;
+            }
+
+            let value = match (Self::get_value(key), default) {
+                (Some(value), Some(default)) if value.is_empty() => default.to_string(),
+                (Some(value), _) => value,
+                (None, Some(default)) => default.to_string(),
+                (None, None) => return Err(OspreyError::MissingEnvValue(key.to_string())),
+            };
+            interpolated.push_str(&value);
+            rest = &after[expression.len() + 1..];
+        }
+        interpolated.push_str(rest);
+        Ok(interpolated)
+    }
+
+    fn is_variable_name(key: &str) -> bool {
+        let mut chars = key.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            }
+            _ => false,
+        }
+    }
+
+    // the value of a secret, read from the file named by KEY_FILE when it's set (docker and
+    // kubernetes secret mounts) or taken from KEY otherwise. the file's trailing newline is dropped
+    pub fn get_secret(key: &str) -> Result<Option<String>, OspreyError> {
+        if let Some(path) = Self::get_value(&format!("{}_FILE", key)) {
+            let secret = fs::read_to_string(path)?;
+            return Ok(Some(secret.trim_end_matches(&['\r', '\n'][..]).to_string()));
+        }
+        Ok(Self::get_value(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every test uses its own variables since tests run in parallel in one process
+    #[test]
+    fn test_parse() {
+        let contents = "\n# local settings\nPOSTGRES_HOST=db\nexport POSTGRES_USER = app\nPOSTGRES_PASSWORD=\"a b=c\"\nPOSTGRES_DB='app'\nnot a variable\n";
+        assert_eq!(
+            Env::parse(contents),
+            vec![
+                ("POSTGRES_HOST".to_string(), "db".to_string()),
+                ("POSTGRES_USER".to_string(), "app".to_string()),
+                ("POSTGRES_PASSWORD".to_string(), "a b=c".to_string()),
+                ("POSTGRES_DB".to_string(), "app".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_file() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(
+            file.path(),
+            "OSPREY_TEST_DOTENV_A=from-file\nOSPREY_TEST_DOTENV_B=from-file\n",
+        )
+        .unwrap();
+        env::set_var("OSPREY_TEST_DOTENV_B", "from-env");
+
+        Env::load_file(file.path()).unwrap();
+        assert_eq!(env::var("OSPREY_TEST_DOTENV_A").unwrap(), "from-file");
+        assert_eq!(env::var("OSPREY_TEST_DOTENV_B").unwrap(), "from-env");
+
+        env::remove_var("OSPREY_TEST_DOTENV_A");
+        env::remove_var("OSPREY_TEST_DOTENV_B");
+    }
+
+    #[test]
+    fn test_get_required() {
+        assert!(matches!(
+            Env::get_required("OSPREY_TEST_REQUIRED"),
+            Err(OspreyError::MissingEnvValue(key)) if key == "OSPREY_TEST_REQUIRED"
+        ));
+
+        env::set_var("OSPREY_TEST_REQUIRED", "value");
+        assert_eq!(Env::get_required("OSPREY_TEST_REQUIRED").unwrap(), "value");
+        env::remove_var("OSPREY_TEST_REQUIRED");
+    }
+
+    #[test]
+    fn test_get_secret() {
+        assert_eq!(Env::get_secret("OSPREY_TEST_SECRET").unwrap(), None);
+
+        env::set_var("OSPREY_TEST_SECRET", "from-env");
+        assert_eq!(
+            Env::get_secret("OSPREY_TEST_SECRET").unwrap(),
+            Some("from-env".to_string())
+        );
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "from-file\n").unwrap();
+        env::set_var("OSPREY_TEST_SECRET_FILE", file.path());
+        assert_eq!(
+            Env::get_secret("OSPREY_TEST_SECRET").unwrap(),
+            Some("from-file".to_string())
+        );
+
+        env::set_var("OSPREY_TEST_SECRET_FILE", "/does/not/exist");
+        assert!(matches!(
+            Env::get_secret("OSPREY_TEST_SECRET"),
+            Err(OspreyError::Io(_))
+        ));
+
+        env::remove_var("OSPREY_TEST_SECRET");
+        env::remove_var("OSPREY_TEST_SECRET_FILE");
+    }
+
+    #[test]
+    fn test_interpolate() {
+        env::set_var("OSPREY_TEST_APP_ROLE", "app_user");
+        env::set_var("OSPREY_TEST_EMPTY_SCHEMA", "");
+        assert_eq!(
+            Env::interpolate("GRANT SELECT ON a TO ${OSPREY_TEST_APP_ROLE};").unwrap(),
+            "GRANT SELECT ON a TO app_user;"
+        );
+        assert_eq!(
+            Env::interpolate(
+                "CREATE SCHEMA ${OSPREY_TEST_EMPTY_SCHEMA:-app} AUTHORIZATION ${OSPREY_TEST_APP_ROLE:-x};"
+            )
+            .unwrap(),
+            "CREATE SCHEMA app AUTHORIZATION app_user;"
+        );
+        assert_eq!(
+            Env::interpolate(
+                "SELECT '${OSPREY_TEST_UNSET_LEVEL:-}' || '${OSPREY_TEST_EMPTY_SCHEMA}';"
+            )
+            .unwrap(),
+            "SELECT '' || '';"
+        );
+
+        // only ${NAME} is substituted
+        let untouched = "SELECT $1, $$ ${ $$, '${not a name}', '${unclosed';";
+        assert_eq!(Env::interpolate(untouched).unwrap(), untouched);
+    }
+
+    #[test]
+    fn test_interpolate_missing_variable() {
+        assert!(matches!(
+            Env::interpolate("GRANT SELECT ON a TO ${OSPREY_TEST_UNSET_ROLE};"),
+            Err(OspreyError::MissingEnvValue(key)) if key == "OSPREY_TEST_UNSET_ROLE"
+        ));
+    }
 }
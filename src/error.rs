@@ -2,34 +2,195 @@ use std::error::Error;
 use std::fmt;
 use std::fmt::Display;
 
+#[cfg(feature = "postgres")]
+pub use native_tls::Error as TlsError;
+#[cfg(feature = "postgres")]
+pub use postgres::Error as PostgresError;
+
+// without the postgres feature nothing can fail with a postgres or tls error, the variants
+// carrying them are kept so matching on OspreyError doesn't depend on the features
+#[cfg(not(feature = "postgres"))]
+#[derive(Debug)]
+pub enum PostgresError {}
+
+#[cfg(not(feature = "postgres"))]
+#[derive(Debug)]
+pub enum TlsError {}
+
+#[cfg(not(feature = "postgres"))]
+impl Display for PostgresError {
+    fn fmt(&self, _: &mut fmt::Formatter) -> fmt::Result {
+        match *self {}
+    }
+}
+
+#[cfg(not(feature = "postgres"))]
+impl Error for PostgresError {}
+
+#[cfg(not(feature = "postgres"))]
+impl Display for TlsError {
+    fn fmt(&self, _: &mut fmt::Formatter) -> fmt::Result {
+        match *self {}
+    }
+}
+
+#[cfg(not(feature = "postgres"))]
+impl Error for TlsError {}
+
 quick_error! {
     #[derive(Debug)]
     pub enum OspreyError {
         NotADirectory{
             display("Not a directory")
         }
+        InvalidVersions(count: usize) {
+            display("Found {} problems with migration versions", count)
+        }
+        InvalidFiles(count: usize) {
+            display("{} migration files could not be parsed", count)
+        }
+        MissingTag(file: String, tag: String) {
+            display("The file {} does not contain the tag {}", file, tag)
+        }
+        MissingFile(name: String) {
+            display("The file for migration {} does not exist", name)
+        }
+        MissingEnvValue(key: String) {
+            display("The environment variable {} is not set", key)
+        }
+        InvalidEnvValue(key: String, value: String) {
+            display("Invalid value {} for environment variable {}", value, key)
+        }
+        InvalidConfig(path: String, message: String) {
+            display("Invalid configuration file {}: {}", path, message)
+        }
+        IncompleteDatabaseConfiguration(missing: String) {
+            display("The database configuration has no {}", missing)
+        }
+        InvalidDatabaseUrl {
+            display("DATABASE_URL is not a valid postgres connection url")
+        }
+        Locked {
+            display("The migrations are locked by someone else")
+        }
+        CopyNotSupported {
+            display("COPY ... FROM STDIN data can only be loaded into postgres")
+        }
+        IncompleteMigrationsTable(table: String, columns: String, err: Box<OspreyError>) {
+            display("The migrations table {} could not be read, it needs the columns {}: {}", table, columns, err)
+            source(&**err)
+        }
+        UnexpectedRecord {
+            display("The migrations table contains a record that could not be read")
+        }
+        InvalidTableName(name: String) {
+            display("Invalid migrations table name: {}", name)
+        }
+        InvalidMigrationName(name: String) {
+            display("Invalid migration name {}, only letters, digits, _ and - are allowed", name)
+        }
+        FileExists(path: String) {
+            display("The file {} already exists", path)
+        }
+        InvalidPattern(pattern: String) {
+            display("Invalid file pattern: {}", pattern)
+        }
+        DuplicateName(name: String, paths: Vec<String>) {
+            display("Migration names have to be unique, these files are all named {}: {}",
+                name, paths.join(", "))
+        }
+        PendingMigrations(count: usize) {
+            display("{} migrations are pending", count)
+        }
+        NothingApplied(tag: String) {
+            display("No migration has been applied with the tag {}", tag)
+        }
+        UnknownTarget(target: String) {
+            display("No migration file matches the target {}", target)
+        }
+        TargetMigrated(target: String) {
+            display("The target {} is already migrated", target)
+        }
+        NotConfirmed(action: String) {
+            display("{} was not confirmed, pass --yes to skip the prompt", action)
+        }
+        QueryFailed(name: String, tag: String, index: usize, query: String, err: Box<OspreyError>) {
+            display("Query {} of {} ({}) failed: {}\n    {}", index, name, tag, err, query)
+            source(&**err)
+        }
         SQLFileError(err: SQLFileError) {
+            display("{}", err)
             source(err)
             from()
         }
-        Postgres(err: postgres::Error) {
+        Postgres(err: PostgresError) {
+            display("{}", err)
             source(err)
             from()
         }
+        Tls(err: TlsError) {
+            display("{}", err)
+            source(err)
+            from()
+        }
+        Database(err: Box<dyn Error + Send + Sync>) {
+            display("{}", err)
+            source(&**err)
+        }
         Io(err: std::io::Error) {
+            display("{}", err)
             source(err)
             from()
         }
         Sanity(err: SanityError){
+            display("{}", err)
             source(err)
             from()
         }
+        SanityErrors(errors: Vec<SanityError>) {
+            display("Found {} sanity problems:{}", errors.len(),
+                errors.iter().map(|err| format!("\n    {}", err)).collect::<String>())
+        }
+        ChangedQuerySets(errors: Vec<SanityError>) {
+            display("Refusing to migrate, {} applied query sets changed since they were migrated:{}\nRestore the files, accept the changes with osprey sanity --fix or pass --allow-changed",
+                errors.len(),
+                errors.iter().map(|err| format!("\n    {}", err)).collect::<String>())
+        }
+    }
+}
+
+// process exit codes, 2 is what clap exits with on invalid arguments or an unknown command
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_SANITY: i32 = 3;
+pub const EXIT_SQL_FILE: i32 = 4;
+pub const EXIT_DATABASE: i32 = 5;
+
+impl OspreyError {
+    // sanity failures, unparsable sql files and database errors each get their own exit code
+    // so scripts can tell them apart, everything else exits with EXIT_FAILURE
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            OspreyError::Sanity(_)
+            | OspreyError::SanityErrors(_)
+            | OspreyError::ChangedQuerySets(_)
+            | OspreyError::MissingTag(_, _)
+            | OspreyError::MissingFile(_)
+            | OspreyError::InvalidVersions(_) => EXIT_SANITY,
+            OspreyError::SQLFileError(_) | OspreyError::InvalidFiles(_) => EXIT_SQL_FILE,
+            OspreyError::Postgres(_) | OspreyError::Database(_) | OspreyError::Tls(_) => {
+                EXIT_DATABASE
+            }
+            OspreyError::QueryFailed(_, _, _, _, err) => err.exit_code(),
+            _ => EXIT_FAILURE,
+        }
     }
 }
 
 #[derive(Debug)]
 pub enum SQLFileError {
     SyntaxError(i32, String),
+    SyntaxErrors(Vec<SQLFileError>),
     CouldNoReadFile,
     CouldNotGetFilename,
 }
@@ -44,6 +205,13 @@ impl fmt::Display for SQLFileError {
                 "SQL File contains a syntax error. Line: {} - {}",
                 line, m
             ),
+            SQLFileError::SyntaxErrors(errors) => {
+                write!(f, "SQL File contains {} syntax errors", errors.len())?;
+                for error in errors {
+                    write!(f, "\n    {}", error)?;
+                }
+                Ok(())
+            }
             SQLFileError::CouldNoReadFile => write!(f, "Could not read file"),
             SQLFileError::CouldNotGetFilename => {
                 write!(f, "Could not determine file's stem name from path")
@@ -58,6 +226,7 @@ pub enum SanityError {
     QuerySetChanged(String, String),
     NoExist(String),
     NotMigrated(String),
+    OutOfOrder(String),
 }
 
 impl Error for SanityError {}
@@ -83,6 +252,86 @@ impl Display for SanityError {
             SanityError::NotMigrated(file) => {
                 write!(f, "The file {} does not exist in the migration table", file)
             }
+            SanityError::OutOfOrder(file) => write!(
+                f,
+                "The file {} was migrated after a file that comes after it",
+                file
+            ),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        let cases = vec![
+            (
+                OspreyError::Sanity(SanityError::NoExist("001_users".to_string())),
+                EXIT_SANITY,
+            ),
+            (
+                OspreyError::MissingTag("001_users".to_string(), "down".to_string()),
+                EXIT_SANITY,
+            ),
+            (
+                OspreyError::MissingFile("001_users".to_string()),
+                EXIT_SANITY,
+            ),
+            (OspreyError::SanityErrors(vec![]), EXIT_SANITY),
+            (OspreyError::ChangedQuerySets(vec![]), EXIT_SANITY),
+            (OspreyError::InvalidVersions(2), EXIT_SANITY),
+            (
+                OspreyError::SQLFileError(SQLFileError::SyntaxError(3, "no tag".to_string())),
+                EXIT_SQL_FILE,
+            ),
+            (OspreyError::InvalidFiles(1), EXIT_SQL_FILE),
+            (
+                OspreyError::Database("connection refused".into()),
+                EXIT_DATABASE,
+            ),
+            (OspreyError::PendingMigrations(2), EXIT_FAILURE),
+            (OspreyError::NotADirectory, EXIT_FAILURE),
+            (OspreyError::InvalidDatabaseUrl, EXIT_FAILURE),
+            (
+                OspreyError::MissingEnvValue("POSTGRES_HOST".to_string()),
+                EXIT_FAILURE,
+            ),
+            (
+                OspreyError::Io(std::io::Error::from(std::io::ErrorKind::NotFound)),
+                EXIT_FAILURE,
+            ),
+        ];
+        for (error, code) in cases {
+            assert_eq!(error.exit_code(), code, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn test_sanity_error_display() {
+        let file = "001_users".to_string();
+        let tag = "up".to_string();
+        assert_eq!(
+            SanityError::NoContainTag(file.clone(), tag.clone()).to_string(),
+            "The file 001_users does not contain the tag up that was originally migrated"
+        );
+        assert_eq!(
+            SanityError::QuerySetChanged(file.clone(), tag).to_string(),
+            "The file 001_users has changed since it was last migrated with the tag up"
+        );
+        assert_eq!(
+            SanityError::NoExist(file.clone()).to_string(),
+            "The file 001_users does not exist but exists in the migration table"
+        );
+        assert_eq!(
+            SanityError::NotMigrated(file.clone()).to_string(),
+            "The file 001_users does not exist in the migration table"
+        );
+        assert_eq!(
+            SanityError::OutOfOrder(file).to_string(),
+            "The file 001_users was migrated after a file that comes after it"
+        );
+    }
+}
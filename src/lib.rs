@@ -0,0 +1,32 @@
+//! Osprey's building blocks. The sql file parser can be used on its own to read
+//! migration files from other tooling:
+//!
+//! ```
+//! use osprey::{ParserConfiguration, SQLFile};
+//!
+//! let text = "-- tag:up\nCREATE TABLE users (id SERIAL);\n-- tag:down\nDROP TABLE users;";
+//! let file = SQLFile::new_from_string("001_users", text, &ParserConfiguration::new()).unwrap();
+//!
+//! let up = file.get_query_set("up").unwrap();
+//! assert_eq!(up.queries, vec!["CREATE TABLE users (id SERIAL);"]);
+//! ```
+
+// quick_error! expands recursively once per OspreyError variant
+#![recursion_limit = "256"]
+
+#[macro_use]
+extern crate quick_error;
+
+pub mod config;
+pub mod database;
+pub mod directory;
+pub mod env;
+pub mod error;
+pub mod migrations;
+pub mod sql_file;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod versions;
+
+pub use error::SQLFileError;
+pub use sql_file::{ParserConfiguration, QuerySet, SQLFile, SyntaxErrorMessage, Tag, TagSummary};
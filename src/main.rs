@@ -1,33 +1,301 @@
-mod database;
-mod directory;
-mod env;
-mod error;
-mod migrations;
-mod sql_file;
-use clap::Parser;
-use database::{PostgresClient, PostgresConfiguration};
-use directory::Directory;
-use env::Env;
-use error::{OspreyError, SanityError};
-use migrations::{DatabaseMigrationRecordStorage, MigrationRecordStorage, Migrations};
-use sql_file::SQLFile;
-
-#[macro_use]
-extern crate quick_error;
+use clap::{ArgMatches, FromArgMatches, IntoApp, Parser, Subcommand};
+use indexmap::IndexMap;
+use log::{debug, error, info, warn, Level, LevelFilter};
+use osprey::config::{Config, DatabaseConfig, DEFAULT_CONFIG_FILE};
+use osprey::database::{
+    DatabaseClient, PostgresClient, PostgresConfiguration, TlsMode, DEFAULT_PORT,
+};
+use osprey::directory::Directory;
+use osprey::env::Env;
+use osprey::error::{OspreyError, SanityError};
+use osprey::migrations::{
+    self, DatabaseMigrationRecordStorage, Hooks, MigrationRecordStorage, MigrationReport,
+    Migrations,
+};
+use osprey::sql_file::{ChecksumAlgorithm, ParserConfiguration, SQLFile};
+#[cfg(feature = "sqlite")]
+use osprey::sqlite::SqliteClient;
+use osprey::versions;
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+// AppContext holds what every command works with
+//   interpolate_env substitutes ${VAR} in queries with environment variables before running them
+//   create_table is false when the migrations table is expected to exist already, so osprey
+//   can run as a role that may not create tables
 struct AppContext<'a> {
     pub record_storage: &'a mut dyn MigrationRecordStorage,
     pub sql_sets: Vec<SQLFile>,
+    pub interpolate_env: bool,
+    pub create_table: bool,
 }
 
-#[derive(Debug)]
+// the migrations of the record storage, the migrations table is created first unless it's
+// expected to exist
+fn open_migrations<'s>(
+    record_storage: &'s mut dyn MigrationRecordStorage,
+    create_table: bool,
+) -> Result<Migrations<'s>, OspreyError> {
+    if create_table {
+        Migrations::new(record_storage)
+    } else {
+        Migrations::with_existing_table(record_storage)
+    }
+}
+
+// the name a migration read from standard input is reported with
+const STDIN_NAME: &str = "stdin";
+
+// tags are matched lowercased
+fn lowercase(value: &str) -> String {
+    value.to_lowercase()
+}
+
+// OutputFormat selects how command results are printed
+//   Text - prose for people
+//   Json - a single json document on stdout, log messages go to stderr instead
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Unknown format {}, expected text or json", s)),
+        }
+    }
+}
+
+// SanityReport holds every problem the sanity check found
+#[derive(Debug, Default)]
+struct SanityReport {
+    problems: Vec<SanityError>,
+}
+
+impl SanityReport {
+    // the problems as the command's error, a single problem is reported on its own
+    fn into_result(mut self) -> Result<(), OspreyError> {
+        match self.problems.len() {
+            0 => Ok(()),
+            1 => Err(OspreyError::Sanity(self.problems.remove(0))),
+            _ => Err(OspreyError::SanityErrors(self.problems)),
+        }
+    }
+}
+
+// SanityProblem is the json form of a SanityError
+#[derive(Debug, PartialEq, Serialize)]
+struct SanityProblem {
+    kind: &'static str,
+    file: String,
+    tag: Option<String>,
+    message: String,
+}
+
+impl From<&SanityError> for SanityProblem {
+    fn from(err: &SanityError) -> Self {
+        let (kind, file, tag) = match err {
+            SanityError::NoContainTag(file, tag) => ("missing_tag", file, Some(tag.clone())),
+            SanityError::QuerySetChanged(file, tag) => ("changed", file, Some(tag.clone())),
+            SanityError::NoExist(file) => ("missing_file", file, None),
+            SanityError::NotMigrated(file) => ("not_migrated", file, None),
+            SanityError::OutOfOrder(file) => ("out_of_order", file, None),
+        };
+        SanityProblem {
+            kind,
+            file: file.clone(),
+            tag,
+            message: err.to_string(),
+        }
+    }
+}
+
+impl Serialize for SanityReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let problems: Vec<SanityProblem> = self.problems.iter().map(SanityProblem::from).collect();
+        let mut report = serializer.serialize_struct("SanityReport", 1)?;
+        report.serialize_field("problems", &problems)?;
+        report.end()
+    }
+}
+
+#[derive(clap::Args, Debug)]
 struct MigrateAppArguments {
+    #[clap(
+        short = 'a',
+        long = "tag",
+        value_name = "TAG",
+        default_value = "up",
+        use_delimiter = true,
+        parse(from_str = lowercase)
+    )]
+    up_keys: Vec<String>,
+    #[clap(long = "no-lock", parse(from_flag = std::ops::Not::not))]
+    lock: bool,
+    #[clap(long)]
+    dry_run: bool,
+    #[clap(long)]
+    strict_versions: bool,
+    #[clap(long, value_name = "NAME_OR_VERSION")]
+    target: Option<String>,
+    #[clap(long, value_name = "N", parse(try_from_str = parse_steps))]
+    steps: Option<usize>,
+    #[clap(long)]
+    allow_changed: bool,
+}
+
+// --steps 0 would migrate nothing, it's rejected rather than read as all
+fn parse_steps(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("--steps has to be at least 1".to_string()),
+        Ok(steps) => Ok(steps),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct StatusAppArguments {
+    #[clap(short = 'a', long = "tag", value_name = "TAG", default_value = "up", parse(from_str = lowercase))]
+    up_key: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct PendingAppArguments {
+    #[clap(short = 'a', long = "tag", value_name = "TAG", default_value = "up", parse(from_str = lowercase))]
+    up_key: String,
+    #[clap(long)]
+    exit_on_pending: bool,
+}
+
+// MigrationState is where a file's tagged query set stands compared to the migrations table
+#[derive(Debug, PartialEq)]
+enum MigrationState {
+    Applied,
+    Changed,
+    Pending,
+    NoTag,
+    NoFile,
+}
+
+impl fmt::Display for MigrationState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self {
+            MigrationState::Applied => "applied",
+            MigrationState::Changed => "changed",
+            MigrationState::Pending => "pending",
+            MigrationState::NoTag => "no tag",
+            MigrationState::NoFile => "no file",
+        };
+        f.pad(state)
+    }
+}
+
+// MigrationStatus holds the state of one migration
+//   hash is the recorded hash when the migration was applied, otherwise the file's hash
+#[derive(Debug, PartialEq)]
+struct MigrationStatus {
+    name: String,
+    state: MigrationState,
+    hash: Option<String>,
+    duration_ms: Option<i64>,
+}
+
+#[derive(clap::Args, Debug)]
+struct BaselineAppArguments {
+    #[clap(short = 'a', long = "tag", value_name = "TAG", default_value = "up", parse(from_str = lowercase))]
+    up_key: String,
+    #[clap(long, use_delimiter = true)]
+    files: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct RollbackAppArguments {
+    #[clap(short = 'a', long = "tag", value_name = "TAG", default_value = "up", parse(from_str = lowercase))]
+    up_key: String,
+    #[clap(long = "down-tag", value_name = "DOWN_TAG", default_value = "down", parse(from_str = lowercase))]
+    down_key: String,
+    #[clap(long, default_value = "1")]
+    steps: usize,
+}
+
+#[derive(clap::Args, Debug)]
+struct RedoAppArguments {
+    #[clap(short = 'a', long = "tag", value_name = "TAG", default_value = "up", parse(from_str = lowercase))]
+    up_key: String,
+    #[clap(long = "down-tag", value_name = "DOWN_TAG", default_value = "down", parse(from_str = lowercase))]
+    down_key: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct ValidateAppArguments {
+    #[clap(
+        value_name = "FILE",
+        help = "Validate only this file, - reads standard input"
+    )]
+    file: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct DropAppArguments {
+    #[clap(short = 'y', long)]
+    yes: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ForgetAppArguments {
+    name: String,
+    #[clap(parse(from_str = lowercase))]
+    tag: String,
+    #[clap(short = 'y', long)]
+    yes: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ForceUnlockAppArguments {
+    #[clap(short = 'y', long)]
+    yes: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ResetAppArguments {
+    #[clap(short = 'a', long = "tag", value_name = "TAG", default_value = "up", parse(from_str = lowercase))]
     up_key: String,
+    #[clap(long = "down-tag", value_name = "DOWN_TAG", default_value = "down", parse(from_str = lowercase))]
+    down_key: String,
+    #[clap(short = 'y', long)]
+    yes: bool,
 }
 
-#[derive(Debug)]
+#[derive(clap::Args, Debug)]
+struct NewAppArguments {
+    name: String,
+    #[clap(short = 'a', long = "tag", value_name = "TAG", default_value = "up", parse(from_str = lowercase))]
+    up_key: String,
+    #[clap(long = "down-tag", value_name = "DOWN_TAG", default_value = "down", parse(from_str = lowercase))]
+    down_key: String,
+}
+
+#[derive(clap::Args, Debug)]
 struct SanityAppArguments {
+    #[clap(short = 'i', long)]
     ignore_new_files: bool,
+    #[clap(long)]
+    fail_fast: bool,
+    #[clap(long)]
+    fix: bool,
 }
 
 struct Osprey {}
@@ -35,170 +303,3037 @@ impl Osprey {
     pub fn migrate(
         app_context: &mut AppContext,
         app_arguments: &MigrateAppArguments,
-    ) -> Result<(), OspreyError> {
-        let mut migrations = Migrations::new(app_context.record_storage)?;
+    ) -> Result<MigrationReport, OspreyError> {
+        let version_issues = versions::check_versions(&app_context.sql_sets);
+        for issue in version_issues.iter() {
+            warn!("{}", issue);
+        }
+        if app_arguments.strict_versions && !version_issues.is_empty() {
+            return Err(OspreyError::InvalidVersions(version_issues.len()));
+        }
+
+        // a dry run doesn't change anything that needs guarding
+        if !app_arguments.lock || app_arguments.dry_run {
+            return Self::apply_pending(app_context, app_arguments);
+        }
+
+        // hold the lock until everything is applied so concurrent runs can't both apply the
+        // same pending migrations
+        Self::locked(app_context, |app_context| {
+            Self::apply_pending(app_context, app_arguments)
+        })
+    }
+
+    // runs f holding the migration lock, every command that changes the migrations table takes
+    // it. the lock is released even if f fails
+    fn locked<T>(
+        app_context: &mut AppContext,
+        f: impl FnOnce(&mut AppContext) -> Result<T, OspreyError>,
+    ) -> Result<T, OspreyError> {
+        app_context.record_storage.lock()?;
+        let result = f(app_context);
+        let unlocked = app_context.record_storage.unlock();
+        let value = result?;
+        unlocked?;
+        Ok(value)
+    }
+
+    fn apply_pending(
+        app_context: &mut AppContext,
+        app_arguments: &MigrateAppArguments,
+    ) -> Result<MigrationReport, OspreyError> {
+        let mut migrations = open_migrations(app_context.record_storage, app_context.create_table)?
+            .interpolate_env(app_context.interpolate_env);
+
+        // a file edited after it was applied won't run again, so its change would be lost
+        let changed =
+            Self::changed_query_sets(&migrations.get_migrations()?, &app_context.sql_sets);
+        if !changed.is_empty() {
+            if !app_arguments.allow_changed {
+                return Err(OspreyError::ChangedQuerySets(changed));
+            }
+            for problem in changed.iter() {
+                warn!("{}", problem);
+            }
+        }
+
+        // every tag is run across all files before the next one, each recorded on its own
+        let sql_sets = &app_context.sql_sets;
+
+        // the target is checked for every tag before anything runs. it only counts as migrated
+        // when it is for every tag it has, otherwise those tags are left out and the rest still
+        // migrate up to it
+        let mut up_keys: Vec<&String> = app_arguments.up_keys.iter().collect();
+        if let Some(target) = &app_arguments.target {
+            let mut migrated = vec![];
+            for up_key in up_keys.iter() {
+                match migrations.plan_to(sql_sets, up_key, target) {
+                    Err(OspreyError::TargetMigrated(_)) => migrated.push(*up_key),
+                    result => {
+                        result?;
+                    }
+                }
+            }
+            let has_target = |up_key: &String| {
+                sql_sets.iter().any(|file| {
+                    file.get_query_set(up_key).is_some() && migrations::is_target(file, target)
+                })
+            };
+            if !migrated.is_empty()
+                && up_keys
+                    .iter()
+                    .all(|up_key| migrated.contains(up_key) || !has_target(up_key))
+            {
+                return Err(OspreyError::TargetMigrated(target.clone()));
+            }
+            up_keys.retain(|up_key| !migrated.contains(up_key));
+        }
+
+        let mut report = MigrationReport {
+            dry_run: app_arguments.dry_run,
+            ..MigrationReport::default()
+        };
+        // the steps are shared by all tags, not counted for each one
+        let mut remaining_steps = app_arguments.steps;
+        for up_key in up_keys {
+            if let Some(steps) = remaining_steps {
+                migrations = migrations.steps(steps);
+            }
+            let tag_report = match (&app_arguments.target, app_arguments.dry_run) {
+                (Some(target), true) => migrations.plan_to(sql_sets, up_key, target)?,
+                (None, true) => migrations.plan(sql_sets, up_key)?,
+                (Some(target), false) => migrations.migrate_to(sql_sets, up_key, target)?,
+                (None, false) => migrations.migrate(sql_sets, up_key)?,
+            };
+            remaining_steps = remaining_steps.map(|steps| steps - tag_report.applied.len());
+            report.append(tag_report);
+        }
 
-        // grab previous migrations with up tag
+        if app_arguments.dry_run {
+            for applied in report.applied.iter() {
+                info!("-- {} ({})", applied.name, applied.tag);
+                let query_set = app_context
+                    .sql_sets
+                    .iter()
+                    .find(|file| file.name == applied.name)
+                    .and_then(|file| file.get_query_set(&applied.tag));
+                for query in query_set.iter().flat_map(|q| q.queries.iter()) {
+                    info!("{}", query);
+                }
+            }
+            info!(
+                "Dry run: {} query sets with {} total queries would be executed",
+                report.applied.len(),
+                report.total_queries
+            );
+            return Ok(report);
+        }
+
+        info!(
+            "Executed {} query sets with {} total queries, {} rows affected",
+            report.applied.len(),
+            report.total_queries,
+            report.total_affected_rows
+        );
+
+        Ok(report)
+    }
+
+    // prints every migration file and migration record and whether it has been applied
+    pub fn status(
+        app_context: &mut AppContext,
+        app_arguments: &StatusAppArguments,
+    ) -> Result<(), OspreyError> {
+        let mut migrations = open_migrations(app_context.record_storage, app_context.create_table)?;
         let migration_instances = migrations.get_migrations_by_tag(&app_arguments.up_key)?;
 
-        let mut executed_query_sets = 0;
-        let mut executed_queries = 0;
+        let statuses = Self::migration_status(
+            &migration_instances,
+            &app_context.sql_sets,
+            &app_arguments.up_key,
+        );
+
+        let width = statuses.iter().map(|s| s.name.len()).max().unwrap_or(0);
+        for status in statuses.iter() {
+            let duration = status
+                .duration_ms
+                .map(|ms| format!("{}ms", ms))
+                .unwrap_or_default();
+            println!(
+                "{:<width$}  {:<7}  {:<64}  {}",
+                status.name,
+                status.state,
+                status.hash.as_deref().unwrap_or("-"),
+                duration,
+                width = width
+            );
+        }
+
+        Ok(())
+    }
+
+    // prints the names of the files with a pending query set of the tag, in the order they
+    // would be migrated, one per line
+    pub fn pending(
+        app_context: &mut AppContext,
+        app_arguments: &PendingAppArguments,
+    ) -> Result<(), OspreyError> {
+        let mut migrations = open_migrations(app_context.record_storage, app_context.create_table)?;
+        let names = Self::pending_names(
+            &migrations.get_migrations()?,
+            &app_context.sql_sets,
+            &app_arguments.up_key,
+        );
+        for name in names.iter() {
+            println!("{}", name);
+        }
+
+        if app_arguments.exit_on_pending && !names.is_empty() {
+            return Err(OspreyError::PendingMigrations(names.len()));
+        }
+        Ok(())
+    }
+
+    fn pending_names(
+        migration_instances: &[migrations::MigrationInstance],
+        sql_files: &[SQLFile],
+        tag: &str,
+    ) -> Vec<String> {
+        migrations::pending_migrations(migration_instances, sql_files, tag)
+            .iter()
+            .map(|(file, _)| file.name.clone())
+            .collect()
+    }
 
-        for file in app_context.sql_sets.iter() {
-            // see if this file has a query set with the given tag
-            if let Some(up_query) = file.query_hash_map.get(&app_arguments.up_key) {
-                // see if this migration set has already happened
-                if migration_instances.iter().any(|x| x.name == file.name) {
-                    continue;
+    // prints every migration record, oldest first
+    pub fn history(app_context: &mut AppContext) -> Result<(), OspreyError> {
+        let mut migrations = open_migrations(app_context.record_storage, app_context.create_table)?;
+        for line in Self::history_lines(migrations.get_migrations()?) {
+            println!("{}", line);
+        }
+        Ok(())
+    }
+
+    // one line per record with its index, name, tag, applied date and the start of its hash
+    fn history_lines(mut migration_instances: Vec<migrations::MigrationInstance>) -> Vec<String> {
+        migration_instances.sort_by_key(|instance| instance.index);
+
+        let name_width = migration_instances
+            .iter()
+            .map(|i| i.name.len())
+            .max()
+            .unwrap_or(0);
+        let tag_width = migration_instances
+            .iter()
+            .map(|i| i.tag.len())
+            .max()
+            .unwrap_or(0);
+        migration_instances
+            .iter()
+            .map(|instance| {
+                format!(
+                    "{:>4}  {:<name_width$}  {:<tag_width$}  {:<10}  {}",
+                    instance.index,
+                    instance.name,
+                    instance.tag,
+                    instance.applied_date.as_deref().unwrap_or("-"),
+                    instance.hash.get(..12).unwrap_or(&instance.hash),
+                    name_width = name_width,
+                    tag_width = tag_width
+                )
+            })
+            .collect()
+    }
+
+    fn migration_status(
+        migration_instances: &[migrations::MigrationInstance],
+        sql_sets: &[SQLFile],
+        up_key: &str,
+    ) -> Vec<MigrationStatus> {
+        let mut statuses: Vec<MigrationStatus> = sql_sets
+            .iter()
+            .map(|file| {
+                let query_set = file.get_query_set(up_key);
+                let instance = migration_instances
+                    .iter()
+                    .find(|x| x.name == file.name && x.tag == up_key);
+
+                let (state, hash, duration_ms) = match (query_set, instance) {
+                    (None, _) => (MigrationState::NoTag, None, None),
+                    (Some(query_set), None) => {
+                        (MigrationState::Pending, Some(query_set.hash.clone()), None)
+                    }
+                    (Some(query_set), Some(instance)) if query_set.matches_hash(&instance.hash) => {
+                        (
+                            MigrationState::Applied,
+                            Some(instance.hash.clone()),
+                            instance.duration_ms,
+                        )
+                    }
+                    (Some(_), Some(instance)) => (
+                        MigrationState::Changed,
+                        Some(instance.hash.clone()),
+                        instance.duration_ms,
+                    ),
+                };
+
+                MigrationStatus {
+                    name: file.name.clone(),
+                    state,
+                    hash,
+                    duration_ms,
                 }
+            })
+            .collect();
+
+        // records whose file is gone, like SanityError::NoExist
+        for instance in migration_instances.iter() {
+            if !sql_sets.iter().any(|file| file.name == instance.name) {
+                statuses.push(MigrationStatus {
+                    name: instance.name.clone(),
+                    state: MigrationState::NoFile,
+                    hash: Some(instance.hash.clone()),
+                    duration_ms: instance.duration_ms,
+                });
+            }
+        }
 
-                // execute all queries in the set with given tag
-                migrations.execute_queries(&up_query.queries)?;
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        statuses
+    }
 
-                executed_queries += up_query.queries.len();
-                executed_query_sets += 1;
+    // records migrations as applied without running their queries, for databases that already
+    // have the schema. only the named files are baselined, or every file when none are named
+    pub fn baseline(
+        app_context: &mut AppContext,
+        app_arguments: &BaselineAppArguments,
+    ) -> Result<(), OspreyError> {
+        Self::locked(app_context, |app_context| {
+            Self::record_pending(app_context, app_arguments)
+        })
+    }
 
-                // record migration
-                migrations.add_migration(&up_query.hash, &file.name, &app_arguments.up_key)?;
+    fn record_pending(
+        app_context: &mut AppContext,
+        app_arguments: &BaselineAppArguments,
+    ) -> Result<(), OspreyError> {
+        for name in app_arguments.files.iter() {
+            let file = app_context
+                .sql_sets
+                .iter()
+                .find(|file| &file.name == name)
+                .ok_or_else(|| OspreyError::MissingFile(name.clone()))?;
+            if file.get_query_set(&app_arguments.up_key).is_none() {
+                return Err(OspreyError::MissingTag(
+                    file.name.clone(),
+                    app_arguments.up_key.clone(),
+                ));
             }
         }
 
-        println!(
-            "Executed {} query sets with {} total queries",
-            executed_query_sets, executed_queries
+        let mut migrations = open_migrations(app_context.record_storage, app_context.create_table)?;
+        let migration_instances = migrations.get_migrations_by_tag(&app_arguments.up_key)?;
+
+        let pending = migrations::pending_migrations(
+            &migration_instances,
+            &app_context.sql_sets,
+            &app_arguments.up_key,
+        );
+
+        let records: Vec<(&str, &str, &str)> = pending
+            .into_iter()
+            .filter(|(file, _)| {
+                app_arguments.files.is_empty() || app_arguments.files.contains(&file.name)
+            })
+            .map(|(file, up_query)| {
+                (
+                    file.name.as_str(),
+                    app_arguments.up_key.as_str(),
+                    up_query.hash.as_str(),
+                )
+            })
+            .collect();
+        migrations.add_migrations(&records)?;
+
+        info!(
+            "Baselined {} query sets without executing them",
+            records.len()
+        );
+
+        Ok(())
+    }
+
+    // undoes the most recently applied migrations by running their down query sets, newest
+    // first, and removing their records
+    pub fn rollback(
+        app_context: &mut AppContext,
+        app_arguments: &RollbackAppArguments,
+    ) -> Result<(), OspreyError> {
+        Self::locked(app_context, |app_context| {
+            Self::revert_latest(app_context, app_arguments)
+        })
+    }
+
+    fn revert_latest(
+        app_context: &mut AppContext,
+        app_arguments: &RollbackAppArguments,
+    ) -> Result<(), OspreyError> {
+        let mut migrations = open_migrations(app_context.record_storage, app_context.create_table)?
+            .interpolate_env(app_context.interpolate_env);
+
+        let migration_instances = migrations.get_migrations_by_tag(&app_arguments.up_key)?;
+        let to_rollback: Vec<_> = migration_instances
+            .iter()
+            .rev()
+            .take(app_arguments.steps)
+            .collect();
+
+        // make sure every migration can be rolled back before touching anything
+        let mut down_query_sets = vec![];
+        for instance in to_rollback.iter() {
+            let file = app_context
+                .sql_sets
+                .iter()
+                .find(|file| file.name == instance.name)
+                .ok_or_else(|| OspreyError::MissingFile(instance.name.clone()))?;
+            let down_query = file.get_query_set(&app_arguments.down_key).ok_or_else(|| {
+                OspreyError::MissingTag(file.name.clone(), app_arguments.down_key.clone())
+            })?;
+            down_query_sets.push(down_query);
+        }
+
+        let hooks = Hooks::from_files(&app_context.sql_sets);
+        let mut executed_queries = 0;
+        for (instance, down_query) in to_rollback.iter().zip(down_query_sets) {
+            migrations.revert_query_set(
+                &hooks.wrap(down_query),
+                &instance.name,
+                &app_arguments.up_key,
+            )?;
+            executed_queries += down_query.queries.len();
+        }
+
+        info!(
+            "Rolled back {} query sets with {} total queries",
+            to_rollback.len(),
+            executed_queries
+        );
+
+        Ok(())
+    }
+
+    // undoes the most recently applied migration of the tag and applies the file's current
+    // query set again, for iterating on the newest migration. the rollback and the new
+    // migration are two separate transactions
+    pub fn redo(
+        app_context: &mut AppContext,
+        app_arguments: &RedoAppArguments,
+    ) -> Result<(), OspreyError> {
+        Self::locked(app_context, |app_context| {
+            Self::redo_latest(app_context, app_arguments)
+        })
+    }
+
+    fn redo_latest(
+        app_context: &mut AppContext,
+        app_arguments: &RedoAppArguments,
+    ) -> Result<(), OspreyError> {
+        let mut migrations = open_migrations(app_context.record_storage, app_context.create_table)?
+            .interpolate_env(app_context.interpolate_env);
+
+        let instance = migrations
+            .get_migrations_by_tag(&app_arguments.up_key)?
+            .pop()
+            .ok_or_else(|| OspreyError::NothingApplied(app_arguments.up_key.clone()))?;
+        let file = app_context
+            .sql_sets
+            .iter()
+            .find(|file| file.name == instance.name)
+            .ok_or_else(|| OspreyError::MissingFile(instance.name.clone()))?;
+        let query_set = |tag: &String| {
+            file.get_query_set(tag)
+                .ok_or_else(|| OspreyError::MissingTag(file.name.clone(), tag.clone()))
+        };
+        let down_query = query_set(&app_arguments.down_key)?;
+        let up_query = query_set(&app_arguments.up_key)?;
+
+        let hooks = Hooks::from_files(&app_context.sql_sets);
+        migrations.revert_query_set(&hooks.wrap(down_query), &file.name, &app_arguments.up_key)?;
+        migrations.apply_query_set(&hooks.wrap(up_query), &file.name, &app_arguments.up_key)?;
+
+        info!(
+            "Redid {} ({}), {} queries",
+            file.name,
+            app_arguments.up_key,
+            up_query.queries.len()
         );
+        Ok(())
+    }
+
+    pub fn drop(app_context: &mut AppContext) -> Result<(), OspreyError> {
+        Self::locked(app_context, Self::drop_table)
+    }
 
+    fn drop_table(app_context: &mut AppContext) -> Result<(), OspreyError> {
+        open_migrations(app_context.record_storage, app_context.create_table)?.drop_table()?;
+        info!("Dropped the migrations table");
         Ok(())
     }
 
+    // removes the record of a single migration, its query set counts as pending again.
+    // nothing is run, the migrated objects stay
+    pub fn forget(
+        app_context: &mut AppContext,
+        app_arguments: &ForgetAppArguments,
+    ) -> Result<(), OspreyError> {
+        Self::locked(app_context, |app_context| {
+            Self::forget_record(app_context, app_arguments)
+        })
+    }
+
+    fn forget_record(
+        app_context: &mut AppContext,
+        app_arguments: &ForgetAppArguments,
+    ) -> Result<(), OspreyError> {
+        let (name, tag) = (&app_arguments.name, &app_arguments.tag);
+        let mut migrations = open_migrations(app_context.record_storage, app_context.create_table)?;
+        if !migrations
+            .get_migrations_by_tag(tag)?
+            .iter()
+            .any(|instance| &instance.name == name)
+        {
+            info!(
+                "There is no record of {} ({}), nothing to forget",
+                name, tag
+            );
+            return Ok(());
+        }
+
+        migrations.remove_migration(name, tag)?;
+        info!("Forgot {} ({}), it's pending again", name, tag);
+        Ok(())
+    }
+
+    // holds the migration lock until a line is read, e.g. enter on the terminal, so nothing
+    // migrates in the meantime
+    pub fn lock<R: BufRead>(app_context: &mut AppContext, mut input: R) -> Result<(), OspreyError> {
+        app_context.record_storage.lock()?;
+        info!("Holding the migration lock, press enter to release it");
+
+        let mut line = String::new();
+        let read = input.read_line(&mut line);
+        app_context.record_storage.unlock()?;
+        read?;
+        info!("Released the migration lock");
+        Ok(())
+    }
+
+    // clears a lock held by someone else, only a forced unlock interrupts a running migration
+    pub fn unlock(app_context: &mut AppContext, force: bool) -> Result<(), OspreyError> {
+        match app_context.record_storage.clear_lock(force)? {
+            0 if force => info!("The migrations weren't locked"),
+            0 => info!(
+                "No idle session holds the migration lock, force-unlock also ends a running one"
+            ),
+            cleared => info!("Ended {} sessions holding the migration lock", cleared),
+        }
+        Ok(())
+    }
+
+    // rolls back everything migrated with the tag, newest first, and then forgets all
+    // migrations. nothing is dropped if any rollback fails
+    pub fn reset(
+        app_context: &mut AppContext,
+        app_arguments: &ResetAppArguments,
+    ) -> Result<(), OspreyError> {
+        let rollback_arguments = RollbackAppArguments {
+            up_key: app_arguments.up_key.clone(),
+            down_key: app_arguments.down_key.clone(),
+            steps: usize::MAX,
+        };
+        Self::locked(app_context, |app_context| {
+            Self::revert_latest(app_context, &rollback_arguments)?;
+            Self::drop_table(app_context)
+        })
+    }
+
+    // the problem with a record of the file, when the file no longer has the record's tag or
+    // the tag's query set has changed since it was migrated
+    fn record_problem(
+        file: &SQLFile,
+        migration: &migrations::MigrationInstance,
+    ) -> Option<SanityError> {
+        match file.get_query_set(&migration.tag) {
+            None => Some(SanityError::NoContainTag(
+                file.name.clone(),
+                migration.tag.clone(),
+            )),
+            // see if the query set is unchanged since the last migration
+            Some(query_set) if !query_set.matches_hash(&migration.hash) => Some(
+                SanityError::QuerySetChanged(file.name.clone(), migration.tag.clone()),
+            ),
+            Some(_) => None,
+        }
+    }
+
+    // the applied query sets whose file changed since, as SanityError::QuerySetChanged
+    fn changed_query_sets(
+        migration_instances: &[migrations::MigrationInstance],
+        sql_sets: &[SQLFile],
+    ) -> Vec<SanityError> {
+        sql_sets
+            .iter()
+            .flat_map(|file| {
+                migration_instances
+                    .iter()
+                    .filter(move |x| x.name == file.name)
+                    .filter_map(move |migration| Self::record_problem(file, migration))
+            })
+            .filter(|problem| matches!(problem, SanityError::QuerySetChanged(_, _)))
+            .collect()
+    }
+
+    // every problem found, or only the first one when failing fast
     fn instance_file_check(
         migration_instances: &[migrations::MigrationInstance],
         sql_sets: &[SQLFile],
         ignore_new_files: bool,
-    ) -> Result<(), SanityError> {
+        fail_fast: bool,
+    ) -> Vec<SanityError> {
+        let mut problems = vec![];
+
         for file in sql_sets.iter() {
             let filtered = migration_instances.iter().filter(|x| x.name == file.name);
             let mut count = 0;
 
             // see if this migration set has already happened
             for migration in filtered {
+                count += 1;
+
                 // check if this file still has the tagged query used in this migration instance
-                let maybe_query_set = file.query_hash_map.get(&migration.tag);
-
-                // this file doesn't have the tagged query, return error
-                if maybe_query_set.is_none() {
-                    return Err(SanityError::NoContainTag(
-                        file.name.clone(),
-                        migration.tag.clone(),
-                    ));
-                }
-                // see if the query set is unchanged since the last migration
-                if maybe_query_set.unwrap().hash != migration.hash {
-                    return Err(SanityError::QuerySetChanged(
-                        file.name.clone(),
-                        migration.tag.clone(),
-                    ));
+                problems.extend(Self::record_problem(file, migration));
+                if fail_fast && !problems.is_empty() {
+                    return problems;
                 }
-                count += 1;
             }
 
-            if !ignore_new_files && count == 0 {
-                return Err(SanityError::NotMigrated(file.name.clone()));
+            if !ignore_new_files && count == 0 && !migrations::is_hook_file(file) {
+                problems.push(SanityError::NotMigrated(file.name.clone()));
+                if fail_fast {
+                    return problems;
+                }
             }
         }
 
         for instance in migration_instances {
-            let mut found = false;
-
-            for sql_file in sql_sets.iter() {
-                if sql_file.name == instance.name {
-                    found = true;
-                    break;
+            if !sql_sets
+                .iter()
+                .any(|sql_file| sql_file.name == instance.name)
+            {
+                problems.push(SanityError::NoExist(instance.name.clone()));
+                if fail_fast {
+                    return problems;
                 }
             }
-            if !found {
-                return Err(SanityError::NoExist(instance.name.clone()));
+        }
+
+        // each tag is migrated in file order, a record of a file that comes before the file of
+        // an earlier record of the tag was applied out of order
+        let mut records: Vec<_> = migration_instances.iter().collect();
+        records.sort_by_key(|instance| instance.index);
+        let mut latest: HashMap<&str, &SQLFile> = HashMap::new();
+        for instance in records {
+            let file = match sql_sets.iter().find(|file| file.name == instance.name) {
+                Some(file) => file,
+                None => continue,
+            };
+            match latest.get(instance.tag.as_str()) {
+                Some(later)
+                    if migrations::migration_order(file) < migrations::migration_order(later) =>
+                {
+                    problems.push(SanityError::OutOfOrder(instance.name.clone()));
+                    if fail_fast {
+                        return problems;
+                    }
+                }
+                _ => {
+                    latest.insert(&instance.tag, file);
+                }
             }
         }
-        Ok(())
+        problems
     }
 
     fn sanity(
         app_context: &mut AppContext,
         app_arguments: &SanityAppArguments,
-    ) -> Result<(), OspreyError> {
-        let mut migrations = Migrations::new(app_context.record_storage)?;
+    ) -> Result<SanityReport, OspreyError> {
+        // only fixing changes the migrations table
+        if !app_arguments.fix {
+            return Self::check_sanity(app_context, app_arguments);
+        }
+        Self::locked(app_context, |app_context| {
+            Self::check_sanity(app_context, app_arguments)
+        })
+    }
+
+    fn check_sanity(
+        app_context: &mut AppContext,
+        app_arguments: &SanityAppArguments,
+    ) -> Result<SanityReport, OspreyError> {
+        let mut migrations = open_migrations(app_context.record_storage, app_context.create_table)?;
         let migration_instances = migrations.get_migrations()?;
 
-        Self::instance_file_check(
+        let mut problems = Self::instance_file_check(
             &migration_instances,
             &app_context.sql_sets,
             app_arguments.ignore_new_files,
-        )?;
-        Ok(())
+            app_arguments.fail_fast,
+        );
+
+        // accept the current hash of changed query sets, every other problem is kept
+        if app_arguments.fix {
+            let mut remaining = vec![];
+            for problem in problems {
+                if let SanityError::QuerySetChanged(name, tag) = &problem {
+                    let query_set = app_context
+                        .sql_sets
+                        .iter()
+                        .find(|file| &file.name == name)
+                        .and_then(|file| file.get_query_set(tag));
+                    if let Some(query_set) = query_set {
+                        migrations.update_migration_hash(&query_set.hash, name, tag)?;
+                        info!("Updated the hash of {} ({})", name, tag);
+                        continue;
+                    }
+                }
+                remaining.push(problem);
+            }
+            problems = remaining;
+        }
+
+        Ok(SanityReport { problems })
     }
-}
 
-#[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
-struct Args {
-    #[clap(short, long, default_value = "./migrations/")]
-    migrations_directory: String,
-    #[clap(short = 't', long, default_value = "_migrations")]
-    migrations_table: String,
-    #[clap(short = 'a', long, default_value = "up")]
-    tag: String,
-    #[clap(short = 'r', long, default_value = "sanity")]
-    run: String,
-    #[clap(short = 'i', long)]
-    ignore_new_files: bool,
+    // creates <utc timestamp>_<name>.sql with an up and a down section. the placeholder queries
+    // keep the file parsable until they're replaced
+    fn new_migration(
+        directory: &Directory,
+        app_arguments: &NewAppArguments,
+        tag_prefix: &str,
+        now: SystemTime,
+    ) -> Result<PathBuf, OspreyError> {
+        let name = &app_arguments.name;
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(OspreyError::InvalidMigrationName(name.clone()));
+        }
+
+        let contents = format!(
+            "-- {prefix}{up}\n-- replace with the migration's queries\nSELECT 1;\n\n-- {prefix}{down}\n-- replace with the queries undoing it\nSELECT 1;\n",
+            prefix = tag_prefix,
+            up = app_arguments.up_key,
+            down = app_arguments.down_key,
+        );
+        let file_name = format!("{}_{}.sql", utc_timestamp(now), name);
+        let path = directory.create_file(&file_name, &contents)?;
+        info!("Created {}", path.display());
+        Ok(path)
+    }
+
+    // parses every file leniently so all of a file's syntax errors are reported at once, and
+    // keeps going after a broken file so a single run reports every broken file
+    fn validate(files: &[PathBuf], configuration: ParserConfiguration) -> Result<(), OspreyError> {
+        let configuration = configuration.lenient(true);
+        let mut invalid = 0;
+        let mut parsed = vec![];
+        for (file, result) in files
+            .iter()
+            .zip(SQLFile::new_from_files(files, &configuration))
+        {
+            match result {
+                Ok(sql_file) => {
+                    debug!("{}", sql_file);
+                    parsed.push((file, sql_file))
+                }
+                Err(err) => {
+                    println!("{}: {}", file.display(), err);
+                    invalid += 1;
+                }
+            }
+        }
+
+        if invalid > 0 {
+            return Err(OspreyError::InvalidFiles(invalid));
+        }
+        check_unique_names(parsed.iter().map(|(path, sql_file)| (*path, sql_file)))?;
+        info!("Validated {} files", files.len());
+        Ok(())
+    }
+
+    // validates a single migration read from a pipe, it's named after its source
+    fn validate_reader<R: BufRead>(
+        reader: R,
+        configuration: ParserConfiguration,
+    ) -> Result<(), OspreyError> {
+        let configuration = configuration.lenient(true);
+        match SQLFile::new_from_reader(STDIN_NAME, reader, &configuration) {
+            Ok(sql_file) => debug!("{}", sql_file),
+            Err(err) => {
+                println!("{}: {}", STDIN_NAME, err);
+                return Err(OspreyError::InvalidFiles(1));
+            }
+        }
+        info!("Validated {}", STDIN_NAME);
+        Ok(())
+    }
+}
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(short, long, parse(from_occurrences))]
+    verbose: u64,
+    #[clap(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    #[clap(
+        long,
+        default_value = "text",
+        possible_values = ["text", "json"],
+        hide_possible_values = true
+    )]
+    format: OutputFormat,
+    #[clap(
+        short = 'm',
+        long = "migrations-directory",
+        value_name = "DIRECTORY",
+        default_value = "./migrations/",
+        use_delimiter = true
+    )]
+    migrations_directories: Vec<String>,
+    #[clap(long)]
+    recursive: bool,
+    #[clap(long, use_delimiter = true)]
+    ignore: Vec<String>,
+    #[clap(short = 't', long, default_value = "_migrations")]
+    migrations_table: String,
+    #[clap(long, default_value = "tag:")]
+    tag_prefix: String,
+    #[clap(long, default_value = ";")]
+    delimiter: String,
+    #[clap(long)]
+    normalize_whitespace: bool,
+    #[clap(long)]
+    interpolate_env: bool,
+    #[clap(long = "no-create-table", parse(from_flag = std::ops::Not::not))]
+    create_table: bool,
+    #[clap(long, value_name = "SUFFIX")]
+    template_suffix: Option<String>,
+    #[clap(
+        long,
+        default_value = "sha256",
+        possible_values = ["sha256", "blake3"],
+        hide_possible_values = true
+    )]
+    checksum_algorithm: ChecksumAlgorithm,
+    #[clap(
+        long,
+        default_value = "disable",
+        possible_values = ["disable", "require"],
+        hide_possible_values = true
+    )]
+    tls: TlsMode,
+    #[clap(long)]
+    tls_ca_cert: Option<String>,
+    #[clap(long)]
+    strict_env: bool,
+    #[clap(long)]
+    env_file: Option<String>,
+    #[clap(long, default_value = "0")]
+    connect_retries: u32,
+    #[clap(long, default_value = "500")]
+    connect_retry_delay: u64,
+    #[clap(long)]
+    connect_timeout: Option<u64>,
+    #[clap(long)]
+    statement_timeout: Option<u64>,
+    #[clap(long, value_name = "SCHEMAS")]
+    search_path: Option<String>,
+    #[clap(long, value_name = "NAME")]
+    application_name: Option<String>,
+    #[clap(long, value_name = "PATH")]
+    config: Option<String>,
+    // the configuration file's [database] table
+    #[clap(skip)]
+    database: DatabaseConfig,
+    #[clap(subcommand)]
+    command: Command,
 }
 
-fn main() -> Result<(), OspreyError> {
-    let args = Args::parse();
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[clap(about = "Apply the pending query sets of a tag")]
+    Migrate(MigrateAppArguments),
+    #[clap(about = "List the applied and pending migrations of a tag")]
+    Status(StatusAppArguments),
+    #[clap(about = "List every migration record with the date it was applied")]
+    History,
+    #[clap(about = "List the files with a pending query set of a tag, one per line")]
+    Pending(PendingAppArguments),
+    #[clap(about = "Record query sets as migrated without running them")]
+    Baseline(BaselineAppArguments),
+    #[clap(about = "Undo the most recently applied migrations")]
+    Rollback(RollbackAppArguments),
+    #[clap(about = "Roll back the most recently applied migration and apply it again")]
+    Redo(RedoAppArguments),
+    #[clap(about = "Drop the migrations table, the migrated tables stay")]
+    Drop(DropAppArguments),
+    #[clap(about = "Roll back every migration of a tag, then drop the migrations table")]
+    Reset(ResetAppArguments),
+    #[clap(about = "Remove the record of a migration without running anything")]
+    Forget(ForgetAppArguments),
+    #[clap(about = "Hold the migration lock until enter is pressed")]
+    Lock,
+    #[clap(about = "Clear the migration lock of an idle session, e.g. a forgotten osprey lock")]
+    Unlock,
+    #[clap(about = "Clear the migration lock, ending a migration that's still running")]
+    ForceUnlock(ForceUnlockAppArguments),
+    #[clap(about = "Check the migrations table against the migration files")]
+    Sanity(SanityAppArguments),
+    #[clap(about = "Parse the migration files without connecting to the database")]
+    Validate(ValidateAppArguments),
+    #[clap(about = "Create a new timestamped migration file", alias = "create")]
+    New(NewAppArguments),
+    #[clap(about = "Check that the database is reachable and answers queries")]
+    Ping,
+}
 
-    // get postgres info from environment variables
-    let dbhost = Env::get_value_or_default("POSTGRES_HOST", "localhost");
-    let password = Env::get_value_or_default("POSTGRES_PASSWORD", "postgres");
-    let username = Env::get_value_or_default("POSTGRES_USER", "postgres");
-    let db_name = Env::get_value_or_default("POSTGRES_DB", "postgres");
+impl Command {
+    // the tag of the commands that take a single one
+    fn tag_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Command::Status(StatusAppArguments { up_key })
+            | Command::Pending(PendingAppArguments { up_key, .. })
+            | Command::Baseline(BaselineAppArguments { up_key, .. })
+            | Command::Rollback(RollbackAppArguments { up_key, .. })
+            | Command::Redo(RedoAppArguments { up_key, .. })
+            | Command::Reset(ResetAppArguments { up_key, .. })
+            | Command::New(NewAppArguments { up_key, .. }) => Some(up_key),
+            _ => None,
+        }
+    }
+}
 
-    // read all .sql files in the directory, parse them
-    let directory_files = Directory::new(&args.migrations_directory)?.get_file_list("sql")?;
-    let mut all_query_sets = vec![];
-    for file in directory_files {
-        let f = SQLFile::new_from_file(&file)?;
-        all_query_sets.push(f);
+// fills in the settings that weren't given on the command line from the configuration file.
+// an explicitly given file has to exist, the default one is optional
+fn apply_config_file(args: &mut Args, matches: &ArgMatches) -> Result<(), OspreyError> {
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None if Path::new(DEFAULT_CONFIG_FILE).is_file() => Config::load(DEFAULT_CONFIG_FILE)?,
+        None => return Ok(()),
+    };
+    apply_config(args, matches, config);
+    Ok(())
+}
+
+fn apply_config(args: &mut Args, matches: &ArgMatches, config: Config) {
+    let given = |matches: &ArgMatches, id: &str| matches.occurrences_of(id) > 0;
+
+    if let Some(directories) = config.migrations_directory {
+        if !given(matches, "migrations-directories") {
+            args.migrations_directories = directories.into_vec();
+        }
     }
+    if let Some(table) = config.migrations_table {
+        if !given(matches, "migrations-table") {
+            args.migrations_table = table;
+        }
+    }
+    if let (Some(tag), Some((_, command_matches))) = (config.tag, matches.subcommand()) {
+        match &mut args.command {
+            Command::Migrate(MigrateAppArguments { up_keys, .. }) => {
+                if !given(command_matches, "up-keys") {
+                    *up_keys = vec![lowercase(&tag)];
+                }
+            }
+            command => {
+                if let Some(up_key) = command.tag_mut() {
+                    if !given(command_matches, "up-key") {
+                        *up_key = lowercase(&tag);
+                    }
+                }
+            }
+        }
+    }
+    args.database = config.database;
+}
+
+// get postgres info from environment variables, falling back to the configuration file's
+// [database] table. a url, from DATABASE_URL or the file, takes precedence over the individual
+// values. in strict mode those must be set explicitly instead of falling back to the local
+// development defaults, only the port keeps its default
+fn postgres_configuration(
+    strict: bool,
+    file: &DatabaseConfig,
+) -> Result<PostgresConfiguration, OspreyError> {
+    if let Some(url) = Env::get_value("DATABASE_URL").or_else(|| file.url.clone()) {
+        return PostgresConfiguration::from_url(&url);
+    }
+
+    // the environment wins over the file, the default is only used when not strict
+    let value = |key: &str, file_value: &Option<String>, default: &str| {
+        if strict {
+            return Env::get_required(key).or_else(|error| file_value.clone().ok_or(error));
+        }
+        Ok(Env::get_value(key)
+            .or_else(|| file_value.clone())
+            .unwrap_or_else(|| default.to_string()))
+    };
 
-    let postgres_configuration = PostgresConfiguration::new()
+    let dbhost = value("POSTGRES_HOST", &file.host, "localhost")?;
+    let port = match Env::get_value("POSTGRES_PORT") {
+        Some(port) => port
+            .parse::<u16>()
+            .map_err(|_| OspreyError::InvalidEnvValue("POSTGRES_PORT".to_string(), port))?,
+        None => file.port.unwrap_or(DEFAULT_PORT),
+    };
+    let password = match Env::get_secret("POSTGRES_PASSWORD")?.or_else(|| file.password.clone()) {
+        Some(password) => password,
+        None if strict => {
+            return Err(OspreyError::MissingEnvValue(
+                "POSTGRES_PASSWORD".to_string(),
+            ))
+        }
+        None => "postgres".to_string(),
+    };
+    let username = value("POSTGRES_USER", &file.user, "postgres")?;
+    let db_name = value("POSTGRES_DB", &file.name, "postgres")?;
+
+    Ok(PostgresConfiguration::new()
         .host(dbhost)
+        .port(port)
         .username(username)
         .password(password)
-        .database_name(db_name);
+        .database_name(db_name))
+}
+
+fn database_client(args: &Args) -> Result<Box<dyn DatabaseClient>, OspreyError> {
+    // sqlite:path/to.db or sqlite:///absolute/path/to.db
+    #[cfg(feature = "sqlite")]
+    if let Some(url) = Env::get_value("DATABASE_URL").or_else(|| args.database.url.clone()) {
+        if let Some(path) = url.strip_prefix("sqlite:") {
+            let path = path.strip_prefix("//").unwrap_or(path);
+            return Ok(Box::new(SqliteClient::open(path)?));
+        }
+    }
+
+    let mut postgres_configuration = postgres_configuration(args.strict_env, &args.database)?
+        .connect_retries(args.connect_retries)
+        .retry_delay(Duration::from_millis(args.connect_retry_delay));
+    // the tls flag can only turn tls on, so it doesn't override sslmode=require in DATABASE_URL
+    if args.tls == TlsMode::Require {
+        postgres_configuration = postgres_configuration.tls_mode(TlsMode::Require);
+    }
+    if let Some(seconds) = args.connect_timeout {
+        postgres_configuration =
+            postgres_configuration.connect_timeout(Duration::from_secs(seconds));
+    }
+    if let Some(seconds) = args.statement_timeout {
+        postgres_configuration =
+            postgres_configuration.statement_timeout(Duration::from_secs(seconds));
+    }
+    if let Some(search_path) = &args.search_path {
+        postgres_configuration = postgres_configuration.search_path(search_path.clone());
+    }
+    if let Some(application_name) = &args.application_name {
+        postgres_configuration = postgres_configuration.application_name(application_name.clone());
+    }
+    if let Some(ca_cert) = &args.tls_ca_cert {
+        postgres_configuration = postgres_configuration.ca_certificate(ca_cert.clone());
+    }
+
+    let postgres_configuration = postgres_configuration.build()?;
+    debug!("Connecting to {}", postgres_configuration.get_display_url());
+    Ok(Box::new(PostgresClient::new(&postgres_configuration)?))
+}
+
+// YYYYMMDDHHMMSS in utc, days are converted to a date with the proleptic gregorian calendar
+fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+    // shift the epoch to 0000-03-01 so leap days fall at the end of a 400 year era
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), OspreyError> {
+    let json = serde_json::to_string_pretty(value).map_err(|err| OspreyError::Io(err.into()))?;
+    println!("{}", json);
+    Ok(())
+}
+
+// info is the default, -v adds debug output and -vv every executed query. quiet only keeps
+// warnings and errors
+fn log_level(verbose: u64, quiet: bool) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+fn main() {
+    let matches = Args::into_app().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+    // info messages are printed as they are, like the rest of the output
+    env_logger::Builder::new()
+        .filter_level(log_level(args.verbose, args.quiet))
+        .target(match args.format {
+            OutputFormat::Text => env_logger::Target::Stdout,
+            OutputFormat::Json => env_logger::Target::Stderr,
+        })
+        .format(|buf, record| match record.level() {
+            Level::Info => writeln!(buf, "{}", record.args()),
+            level => writeln!(buf, "{}: {}", level, record.args()),
+        })
+        .init();
+    if let Err(err) = apply_config_file(&mut args, &matches).and_then(|()| run(args)) {
+        eprintln!("Error: {}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+// the .sql files of the directories, and the .sql files with the template suffix after .sql.
+// directories are listed in the order they were given, each one sorted by path. migrations
+// still run in name order, so the order only decides which path a duplicate name reports first
+fn migration_files(
+    directories: &[Directory],
+    template_suffix: Option<&str>,
+) -> Result<Vec<PathBuf>, OspreyError> {
+    let mut all_files = vec![];
+    for directory in directories {
+        let mut files = directory.get_file_list("sql")?;
+        if let Some(suffix) = template_suffix {
+            let templates = directory.get_file_list(suffix.trim_start_matches('.'))?;
+            files.extend(templates.into_iter().filter(|path| {
+                path.to_str()
+                    .and_then(|path| path.strip_suffix(suffix))
+                    .is_some_and(|path| path.ends_with(".sql"))
+            }));
+            files.sort();
+        }
+        all_files.extend(files);
+    }
+    Ok(all_files)
+}
+
+// records are matched to files by name, two files with the same name would share records
+fn check_unique_names<'a>(
+    files: impl IntoIterator<Item = (&'a PathBuf, &'a SQLFile)>,
+) -> Result<(), OspreyError> {
+    let mut paths_by_name: IndexMap<&str, Vec<&PathBuf>> = IndexMap::new();
+    for (path, sql_file) in files {
+        paths_by_name.entry(&sql_file.name).or_default().push(path);
+    }
+
+    match paths_by_name.into_iter().find(|(_, paths)| paths.len() > 1) {
+        Some((name, paths)) => Err(OspreyError::DuplicateName(
+            name.to_string(),
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+        )),
+        None => Ok(()),
+    }
+}
+
+// asks a yes or no question on the terminal, anything but yes is a no
+fn confirm(question: &str) -> Result<bool, OspreyError> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn run(args: Args) -> Result<(), OspreyError> {
+    // an explicitly given env file has to exist, the default one is optional
+    match &args.env_file {
+        Some(path) => Env::load_file(path)?,
+        None if Path::new(".env").is_file() => Env::load_file(".env")?,
+        None => {}
+    }
+
+    // read all .sql files in the directory, parse them
+    let parser_configuration = ParserConfiguration::new()
+        .tag_prefix(args.tag_prefix.clone())
+        .delimiter(args.delimiter.clone())
+        .normalize_whitespace(args.normalize_whitespace)
+        .checksum_algorithm(args.checksum_algorithm);
+    let parser_configuration = match &args.template_suffix {
+        Some(suffix) => parser_configuration.template_suffix(suffix.clone()),
+        None => parser_configuration,
+    };
+    match &args.command {
+        Command::Validate(ValidateAppArguments { file: Some(file) }) if file == "-" => {
+            return Osprey::validate_reader(std::io::stdin().lock(), parser_configuration)
+        }
+        Command::Validate(ValidateAppArguments { file: Some(file) }) => {
+            return Osprey::validate(&[PathBuf::from(file)], parser_configuration)
+        }
+        Command::Ping => {
+            let started = Instant::now();
+            database_client(&args)?.ping()?;
+            info!(
+                "The database answered in {} ms",
+                started.elapsed().as_millis()
+            );
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let directories = args
+        .migrations_directories
+        .iter()
+        .map(|path| {
+            Directory::new(path)?
+                .recursive(args.recursive)
+                .exclude(&args.ignore)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match &args.command {
+        // new files go to the first directory
+        Command::New(app_arguments) => {
+            Osprey::new_migration(
+                &directories[0],
+                app_arguments,
+                &args.tag_prefix,
+                SystemTime::now(),
+            )?;
+            return Ok(());
+        }
+        Command::Validate(_) => {
+            return Osprey::validate(
+                &migration_files(&directories, args.template_suffix.as_deref())?,
+                parser_configuration,
+            )
+        }
+        Command::Drop(DropAppArguments { yes: false }) => {
+            let question = format!("Drop the migrations table {}?", args.migrations_table);
+            if !confirm(&question)? {
+                return Err(OspreyError::NotConfirmed("Dropping".to_string()));
+            }
+        }
+        Command::Forget(ForgetAppArguments {
+            name,
+            tag,
+            yes: false,
+        }) => {
+            let question = format!("Forget the migration record of {} ({})?", name, tag);
+            if !confirm(&question)? {
+                return Err(OspreyError::NotConfirmed("Forgetting".to_string()));
+            }
+        }
+        Command::ForceUnlock(ForceUnlockAppArguments { yes: false }) => {
+            let question =
+                "End the sessions holding the migration lock, rolling back their migrations?";
+            if !confirm(question)? {
+                return Err(OspreyError::NotConfirmed("Unlocking".to_string()));
+            }
+        }
+        Command::Reset(ResetAppArguments { yes: false, .. }) => {
+            let question = format!(
+                "Roll back every migration and drop the migrations table {}?",
+                args.migrations_table
+            );
+            if !confirm(&question)? {
+                return Err(OspreyError::NotConfirmed("Resetting".to_string()));
+            }
+        }
+        _ => {}
+    }
+
+    let directory_files = migration_files(&directories, args.template_suffix.as_deref())?;
 
-    let mut dbclient = PostgresClient::new(&postgres_configuration)?;
+    let mut all_query_sets = vec![];
+    let mut invalid = 0;
+    for (file, result) in directory_files.iter().zip(SQLFile::new_from_files(
+        &directory_files,
+        &parser_configuration,
+    )) {
+        match result {
+            Ok(sql_file) => all_query_sets.push(sql_file),
+            Err(err) => {
+                error!("{}: {}", file.display(), err);
+                invalid += 1;
+            }
+        }
+    }
+    if invalid > 0 {
+        return Err(OspreyError::InvalidFiles(invalid));
+    }
+    check_unique_names(directory_files.iter().zip(all_query_sets.iter()))?;
+
+    let mut dbclient = database_client(&args)?;
     let mut db_record_storage =
-        DatabaseMigrationRecordStorage::new(&args.migrations_table, &mut dbclient);
+        DatabaseMigrationRecordStorage::new(&args.migrations_table, dbclient.as_mut())?;
 
     let mut app_context = AppContext {
         record_storage: &mut db_record_storage,
         sql_sets: all_query_sets,
+        interpolate_env: args.interpolate_env,
+        create_table: args.create_table,
     };
 
-    match args.run.as_str() {
-        "migrate" => {
-            let app_arguments = MigrateAppArguments { up_key: args.tag };
-            Osprey::migrate(&mut app_context, &app_arguments)?;
+    match &args.command {
+        Command::Migrate(app_arguments) => {
+            let report = Osprey::migrate(&mut app_context, app_arguments)?;
+            if args.format == OutputFormat::Json {
+                print_json(&report)?;
+            }
         }
-        "sanity" => {
-            let app_arguments = SanityAppArguments {
-                ignore_new_files: args.ignore_new_files,
-            };
-            Osprey::sanity(&mut app_context, &app_arguments)?;
+        Command::Status(app_arguments) => Osprey::status(&mut app_context, app_arguments)?,
+        Command::History => Osprey::history(&mut app_context)?,
+        Command::Pending(app_arguments) => Osprey::pending(&mut app_context, app_arguments)?,
+        Command::Baseline(app_arguments) => Osprey::baseline(&mut app_context, app_arguments)?,
+        Command::Rollback(app_arguments) => Osprey::rollback(&mut app_context, app_arguments)?,
+        Command::Redo(app_arguments) => Osprey::redo(&mut app_context, app_arguments)?,
+        Command::Drop(_) => Osprey::drop(&mut app_context)?,
+        Command::Reset(app_arguments) => Osprey::reset(&mut app_context, app_arguments)?,
+        Command::Forget(app_arguments) => Osprey::forget(&mut app_context, app_arguments)?,
+        Command::Lock => Osprey::lock(&mut app_context, std::io::stdin().lock())?,
+        Command::Unlock => Osprey::unlock(&mut app_context, false)?,
+        Command::ForceUnlock(_) => Osprey::unlock(&mut app_context, true)?,
+        Command::Sanity(app_arguments) => {
+            let report = Osprey::sanity(&mut app_context, app_arguments)?;
+            if args.format == OutputFormat::Json {
+                print_json(&report)?;
+            }
+            report.into_result()?;
+        }
+        Command::Validate(_) | Command::New(_) | Command::Ping => {
+            unreachable!("handled before connecting to the database")
         }
-        _ => println!("Unrecognized Command"),
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use osprey::migrations::{InMemoryMigrationRecordStorage, MigrationInstance};
+
+    fn sql_files() -> Vec<SQLFile> {
+        let configuration = ParserConfiguration::new();
+        vec![
+            SQLFile::new_from_string(
+                "001_users",
+                "-- tag:up\nCREATE TABLE users (id INT);\n-- tag:down\nDROP TABLE users;",
+                &configuration,
+            )
+            .unwrap(),
+            SQLFile::new_from_string(
+                "002_posts",
+                "-- tag:up\nCREATE TABLE posts (id INT);\nCREATE INDEX posts_id ON posts (id);\n-- tag:down\nDROP TABLE posts;",
+                &configuration,
+            )
+            .unwrap(),
+        ]
+    }
+
+    fn app_context(
+        record_storage: &mut dyn MigrationRecordStorage,
+        sql_sets: Vec<SQLFile>,
+    ) -> AppContext<'_> {
+        AppContext {
+            record_storage,
+            sql_sets,
+            interpolate_env: false,
+            create_table: true,
+        }
+    }
+
+    fn migrate_arguments() -> MigrateAppArguments {
+        MigrateAppArguments {
+            up_keys: vec!["up".to_string()],
+            lock: true,
+            dry_run: false,
+            strict_versions: false,
+            target: None,
+            steps: None,
+            allow_changed: false,
+        }
+    }
+
+    fn migrate(storage: &mut InMemoryMigrationRecordStorage, sql_sets: Vec<SQLFile>) {
+        let mut app_context = app_context(storage, sql_sets);
+        let app_arguments = migrate_arguments();
+        Osprey::migrate(&mut app_context, &app_arguments).unwrap();
+    }
+
+    fn rollback(
+        storage: &mut InMemoryMigrationRecordStorage,
+        sql_sets: Vec<SQLFile>,
+        steps: usize,
+    ) -> Result<(), OspreyError> {
+        let mut app_context = app_context(storage, sql_sets);
+        let app_arguments = RollbackAppArguments {
+            up_key: "up".to_string(),
+            down_key: "down".to_string(),
+            steps,
+        };
+        Osprey::rollback(&mut app_context, &app_arguments)
+    }
+
+    fn sanity(
+        storage: &mut InMemoryMigrationRecordStorage,
+        sql_sets: Vec<SQLFile>,
+    ) -> Result<(), OspreyError> {
+        let mut app_context = app_context(storage, sql_sets);
+        let app_arguments = SanityAppArguments {
+            ignore_new_files: false,
+            fail_fast: false,
+            fix: false,
+        };
+        Osprey::sanity(&mut app_context, &app_arguments)?.into_result()
+    }
+
+    #[test]
+    fn test_migrate_records_instances() {
+        let files = sql_files();
+        let users_hash = files[0].get_query_set("up").unwrap().hash.clone();
+        let posts_hash = files[1].get_query_set("up").unwrap().hash.clone();
+
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, files);
+
+        // every applied migration records how long it took
+        for record in storage.records.iter_mut() {
+            assert!(record.duration_ms.unwrap() >= 0);
+            record.duration_ms = None;
+        }
+        assert_eq!(
+            storage.records,
+            vec![
+                MigrationInstance::new(1, "001_users", "up", &users_hash),
+                MigrationInstance::new(2, "002_posts", "up", &posts_hash),
+            ]
+        );
+        assert_eq!(storage.executed_queries.len(), 3);
+    }
+
+    #[test]
+    fn test_migrate_skips_applied_files() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files());
+        migrate(&mut storage, sql_files());
+
+        assert_eq!(storage.records.len(), 2);
+        assert_eq!(storage.executed_queries.len(), 3);
+    }
+
+    #[test]
+    fn test_migrate_new_tag_of_migrated_file() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files());
+
+        let files = vec![SQLFile::new_from_string(
+            "001_users",
+            "-- tag:up\nCREATE TABLE users (id INT);\n-- tag:down\nDROP TABLE users;\n-- tag:seed\nINSERT INTO users VALUES (1);",
+            &ParserConfiguration::new(),
+        )
+        .unwrap()];
+        let mut app_context = app_context(&mut storage, files);
+        let app_arguments = MigrateAppArguments {
+            up_keys: vec!["seed".to_string()],
+            ..migrate_arguments()
+        };
+        Osprey::migrate(&mut app_context, &app_arguments).unwrap();
+        Osprey::migrate(&mut app_context, &app_arguments).unwrap();
+
+        let seeded: Vec<_> = storage.records.iter().filter(|r| r.tag == "seed").collect();
+        assert_eq!(seeded.len(), 1);
+        assert_eq!(seeded[0].name, "001_users");
+        assert_eq!(
+            storage.executed_queries.last().unwrap(),
+            "INSERT INTO users VALUES (1);"
+        );
+    }
+
+    #[test]
+    fn test_migrate_several_tags_with_steps() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        let parse =
+            |name, text| SQLFile::new_from_string(name, text, &ParserConfiguration::new()).unwrap();
+        let files = vec![
+            parse(
+                "001_users",
+                "-- tag:up\nCREATE TABLE users (id INT);\n-- tag:seed\nINSERT INTO users VALUES (1);",
+            ),
+            parse("002_posts", "-- tag:up\nCREATE TABLE posts (id INT);"),
+        ];
+        let mut app_context = app_context(&mut storage, files);
+        let app_arguments = MigrateAppArguments {
+            up_keys: vec!["up".to_string(), "seed".to_string()],
+            steps: Some(2),
+            ..migrate_arguments()
+        };
+        // both steps go to the up tag, nothing is left for seed
+        let report = Osprey::migrate(&mut app_context, &app_arguments).unwrap();
+        assert_eq!(report.applied.len(), 2);
+        assert!(report.applied.iter().all(|applied| applied.tag == "up"));
+
+        let report = Osprey::migrate(&mut app_context, &app_arguments).unwrap();
+        assert_eq!(report.applied.len(), 1);
+        assert_eq!(report.applied[0].tag, "seed");
+        assert_eq!(storage.records.len(), 3);
+    }
+
+    #[test]
+    fn test_migrate_several_tags_to_unknown_target() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        let parse =
+            |name, text| SQLFile::new_from_string(name, text, &ParserConfiguration::new()).unwrap();
+        let files = vec![
+            parse(
+                "001_users",
+                "-- tag:up\nCREATE TABLE users (id INT);\n-- tag:seed\nINSERT INTO users VALUES (1);",
+            ),
+            parse("002_posts", "-- tag:up\nCREATE TABLE posts (id INT);"),
+        ];
+        let mut app_context = app_context(&mut storage, files);
+        let app_arguments = MigrateAppArguments {
+            up_keys: vec!["up".to_string(), "seed".to_string()],
+            target: Some("003_tags".to_string()),
+            ..migrate_arguments()
+        };
+        match Osprey::migrate(&mut app_context, &app_arguments) {
+            Err(OspreyError::UnknownTarget(target)) => assert_eq!(target, "003_tags"),
+            other => panic!("expected UnknownTarget, got {:?}", other.map(|_| ())),
+        }
+        assert!(storage.records.is_empty());
+        assert!(storage.executed_queries.is_empty());
+    }
+
+    #[test]
+    fn test_migrate_several_tags_to_target() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        let parse =
+            |name, text| SQLFile::new_from_string(name, text, &ParserConfiguration::new()).unwrap();
+        // only the up tag has a 002 file
+        let files = vec![
+            parse(
+                "001_users",
+                "-- tag:up\nCREATE TABLE users (id INT);\n-- tag:seed\nINSERT INTO users VALUES (1);",
+            ),
+            parse("002_posts", "-- tag:up\nCREATE TABLE posts (id INT);"),
+            parse(
+                "003_tags",
+                "-- tag:up\nCREATE TABLE tags (id INT);\n-- tag:seed\nINSERT INTO tags VALUES (1);",
+            ),
+        ];
+        let mut app_context = app_context(&mut storage, files);
+        let migrate_to = |app_context: &mut AppContext, up_keys: &[&str], target: &str| {
+            let app_arguments = MigrateAppArguments {
+                up_keys: up_keys.iter().map(|up_key| up_key.to_string()).collect(),
+                target: Some(target.to_string()),
+                ..migrate_arguments()
+            };
+            Osprey::migrate(app_context, &app_arguments)
+        };
+
+        // migrated for up only, seed still goes up to it
+        migrate_to(&mut app_context, &["up"], "001_users").unwrap();
+        let report = migrate_to(&mut app_context, &["up", "seed"], "001_users").unwrap();
+        assert_eq!(report.applied.len(), 1);
+        assert_eq!(report.applied[0].tag, "seed");
+
+        // seed has no 002 file, it stops before where 002 would be
+        let report = migrate_to(&mut app_context, &["up", "seed"], "002_posts").unwrap();
+        let applied: Vec<_> = report
+            .applied
+            .iter()
+            .map(|applied| (applied.name.as_str(), applied.tag.as_str()))
+            .collect();
+        assert_eq!(applied, vec![("002_posts", "up")]);
+
+        // migrated for every tag that has it
+        assert!(matches!(
+            migrate_to(&mut app_context, &["up", "seed"], "002_posts"),
+            Err(OspreyError::TargetMigrated(target)) if target == "002_posts"
+        ));
+
+        migrate_to(&mut app_context, &["up", "seed"], "003_tags").unwrap();
+        assert_eq!(storage.records.len(), 5);
+    }
+
+    #[test]
+    fn test_migrate_several_tags() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        let parse =
+            |name, text| SQLFile::new_from_string(name, text, &ParserConfiguration::new()).unwrap();
+        // the second file has nothing to seed
+        let files = vec![
+            parse(
+                "001_users",
+                "-- tag:up\nCREATE TABLE users (id INT);\n-- tag:seed\nINSERT INTO users VALUES (1);",
+            ),
+            parse("002_posts", "-- tag:up\nCREATE TABLE posts (id INT);"),
+        ];
+        let mut app_context = app_context(&mut storage, files);
+        let app_arguments = MigrateAppArguments {
+            up_keys: vec!["up".to_string(), "seed".to_string()],
+            ..migrate_arguments()
+        };
+        let report = Osprey::migrate(&mut app_context, &app_arguments).unwrap();
+        assert_eq!(report.applied.len(), 3);
+        assert_eq!(report.total_queries, 3);
+
+        let records: Vec<_> = storage
+            .records
+            .iter()
+            .map(|r| (r.name.as_str(), r.tag.as_str()))
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                ("001_users", "up"),
+                ("002_posts", "up"),
+                ("001_users", "seed")
+            ]
+        );
+        assert_eq!(
+            storage.executed_queries.last().unwrap(),
+            "INSERT INTO users VALUES (1);"
+        );
+    }
+
+    #[test]
+    fn test_forget() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files());
+        let mut app_context = app_context(&mut storage, sql_files());
+        let forget = |name: &str, tag: &str| ForgetAppArguments {
+            name: name.to_string(),
+            tag: tag.to_string(),
+            yes: true,
+        };
+        Osprey::forget(&mut app_context, &forget("001_users", "up")).unwrap();
+        // unknown names and tags are left alone
+        Osprey::forget(&mut app_context, &forget("001_users", "up")).unwrap();
+        Osprey::forget(&mut app_context, &forget("002_posts", "seed")).unwrap();
+        Osprey::forget(&mut app_context, &forget("missing", "up")).unwrap();
+
+        let records: Vec<_> = storage.records.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(records, vec!["002_posts"]);
+        // nothing was run
+        assert_eq!(storage.executed_queries.len(), 3);
+    }
+
+    #[test]
+    fn test_forget_then_migrate() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files());
+        let mut app_context = app_context(&mut storage, sql_files());
+        let app_arguments = ForgetAppArguments {
+            name: "001_users".to_string(),
+            tag: "up".to_string(),
+            yes: true,
+        };
+        Osprey::forget(&mut app_context, &app_arguments).unwrap();
+
+        // the forgotten file runs again and is recorded after 002_posts
+        migrate(&mut storage, sql_files());
+        let records: Vec<_> = storage
+            .records
+            .iter()
+            .map(|r| (r.name.as_str(), r.index))
+            .collect();
+        assert_eq!(records, vec![("002_posts", 2), ("001_users", 3)]);
+    }
+
+    #[test]
+    fn test_lock_and_unlock() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        let mut app_context = app_context(&mut storage, sql_files());
+        // released once enter is pressed
+        Osprey::lock(&mut app_context, "\n".as_bytes()).unwrap();
+        Osprey::lock(&mut app_context, "".as_bytes()).unwrap();
+
+        // a lock left behind keeps everyone else from migrating until it's cleared
+        app_context.record_storage.lock().unwrap();
+        assert!(matches!(
+            Osprey::lock(&mut app_context, "\n".as_bytes()),
+            Err(OspreyError::Locked)
+        ));
+        Osprey::unlock(&mut app_context, true).unwrap();
+        Osprey::lock(&mut app_context, "\n".as_bytes()).unwrap();
+        assert!(!storage.locked);
+    }
+
+    #[test]
+    fn test_held_lock_blocks_changes() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files());
+        storage.locked = true;
+
+        assert!(matches!(
+            rollback(&mut storage, sql_files(), 1),
+            Err(OspreyError::Locked)
+        ));
+        let mut app_context = app_context(&mut storage, sql_files());
+        let app_arguments = RedoAppArguments {
+            up_key: "up".to_string(),
+            down_key: "down".to_string(),
+        };
+        assert!(matches!(
+            Osprey::redo(&mut app_context, &app_arguments),
+            Err(OspreyError::Locked)
+        ));
+        assert_eq!(storage.records.len(), 2);
+        assert_eq!(storage.executed_queries.len(), 3);
+
+        // the lock is released after the rollback, whether it succeeded or not
+        storage.locked = false;
+        rollback(&mut storage, sql_files(), 1).unwrap();
+        assert!(!storage.locked);
+        assert_eq!(storage.records.len(), 1);
+    }
+
+    fn baseline(
+        storage: &mut InMemoryMigrationRecordStorage,
+        sql_sets: Vec<SQLFile>,
+        files: &[&str],
+    ) -> Result<(), OspreyError> {
+        let mut app_context = app_context(storage, sql_sets);
+        let app_arguments = BaselineAppArguments {
+            up_key: "up".to_string(),
+            files: files.iter().map(|f| f.to_string()).collect(),
+        };
+        Osprey::baseline(&mut app_context, &app_arguments)
+    }
+
+    #[test]
+    fn test_baseline_then_migrate_skips() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        baseline(&mut storage, sql_files(), &["001_users"]).unwrap();
+
+        assert_eq!(storage.records.len(), 1);
+        assert_eq!(storage.records[0].name, "001_users");
+        assert_eq!(storage.records[0].duration_ms, None);
+        assert!(storage.executed_queries.is_empty());
+
+        migrate(&mut storage, sql_files());
+        assert_eq!(
+            storage.executed_queries,
+            vec![
+                "CREATE TABLE posts (id INT);",
+                "CREATE INDEX posts_id ON posts (id);"
+            ]
+        );
+        assert!(sanity(&mut storage, sql_files()).is_ok());
+    }
+
+    #[test]
+    fn test_baseline_all_files() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        baseline(&mut storage, sql_files(), &[]).unwrap();
+        baseline(&mut storage, sql_files(), &[]).unwrap();
+
+        assert_eq!(storage.records.len(), 2);
+        assert!(storage.executed_queries.is_empty());
+        assert!(sanity(&mut storage, sql_files()).is_ok());
+    }
+
+    #[test]
+    fn test_baseline_unknown_file() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        assert!(matches!(
+            baseline(&mut storage, sql_files(), &["003_missing"]),
+            Err(OspreyError::MissingFile(name)) if name == "003_missing"
+        ));
+        assert!(storage.records.is_empty());
+    }
+
+    #[test]
+    fn test_migrate_in_name_order() {
+        let configuration = ParserConfiguration::new();
+        let files: Vec<_> = ["003_c", "001_a", "004_d", "002_b"]
+            .iter()
+            .map(|name| {
+                let text = format!("-- tag:up\nCREATE TABLE {} (id INT);", &name[4..]);
+                SQLFile::new_from_string(name, &text, &configuration).unwrap()
+            })
+            .collect();
+
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, files);
+
+        let names: Vec<_> = storage.records.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["001_a", "002_b", "003_c", "004_d"]);
+        assert_eq!(storage.executed_queries[0], "CREATE TABLE a (id INT);");
+    }
+
+    #[test]
+    fn test_utc_timestamp() {
+        let at = |seconds| utc_timestamp(UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(at(0), "19700101000000");
+        assert_eq!(at(951_782_400), "20000229000000");
+        assert_eq!(at(1_705_321_845), "20240115123045");
+        assert_eq!(at(1_735_689_599), "20241231235959");
+    }
+
+    #[test]
+    fn test_new_migration() {
+        let dir = tempfile::tempdir().unwrap();
+        let directory = Directory::new(dir.path().to_str().unwrap()).unwrap();
+        let app_arguments = NewAppArguments {
+            name: "add_users".to_string(),
+            up_key: "up".to_string(),
+            down_key: "down".to_string(),
+        };
+        let now = UNIX_EPOCH + Duration::from_secs(1_705_321_800);
+
+        let path = Osprey::new_migration(&directory, &app_arguments, "tag:", now).unwrap();
+        assert_eq!(path, dir.path().join("20240115123000_add_users.sql"));
+
+        let sql_file = SQLFile::new_from_file(&path, &ParserConfiguration::new()).unwrap();
+        assert_eq!(sql_file.name, "20240115123000_add_users");
+        assert!(sql_file.get_query_set("up").is_some());
+        assert!(sql_file.get_query_set("down").is_some());
+
+        assert!(matches!(
+            Osprey::new_migration(&directory, &app_arguments, "tag:", now),
+            Err(OspreyError::FileExists(_))
+        ));
+
+        let app_arguments = NewAppArguments {
+            name: "../users".to_string(),
+            up_key: "up".to_string(),
+            down_key: "down".to_string(),
+        };
+        assert!(matches!(
+            Osprey::new_migration(&directory, &app_arguments, "tag:", now),
+            Err(OspreyError::InvalidMigrationName(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("001_good.sql");
+        let broken = dir.path().join("002_broken.sql");
+        std::fs::write(&good, "-- tag:up\nSELECT 1;\n-- tag:down\nSELECT 2;").unwrap();
+        std::fs::write(
+            &broken,
+            "SELECT 1;\n-- tag:up\nSELECT 2;\n-- tag:up\nSELECT 3;",
+        )
+        .unwrap();
+
+        let files = vec![good, broken];
+        assert!(Osprey::validate(&files[..1], ParserConfiguration::new()).is_ok());
+        assert!(matches!(
+            Osprey::validate(&files, ParserConfiguration::new()),
+            Err(OspreyError::InvalidFiles(1))
+        ));
+    }
+
+    #[test]
+    fn test_migration_files_with_template_suffix() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["001_a.sql", "002_b.sql.j2", "macros.j2", "003_c.sql"] {
+            std::fs::write(dir.path().join(name), "-- tag:up\nSELECT 1;").unwrap();
+        }
+        let directories = [Directory::new(dir.path().to_str().unwrap()).unwrap()];
+
+        let names = |files: Vec<PathBuf>| -> Vec<String> {
+            files
+                .iter()
+                .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(
+            names(migration_files(&directories, None).unwrap()),
+            vec!["001_a.sql", "003_c.sql"]
+        );
+        assert_eq!(
+            names(migration_files(&directories, Some(".j2")).unwrap()),
+            vec!["001_a.sql", "002_b.sql.j2", "003_c.sql"]
+        );
+    }
+
+    #[test]
+    fn test_migration_files_of_several_directories() {
+        let users = tempfile::tempdir().unwrap();
+        let billing = tempfile::tempdir().unwrap();
+        for name in ["002_users.sql", "001_accounts.sql"] {
+            std::fs::write(users.path().join(name), "-- tag:up\nSELECT 1;").unwrap();
+        }
+        for name in ["001_invoices.sql", "003_payments.sql"] {
+            std::fs::write(billing.path().join(name), "-- tag:up\nSELECT 1;").unwrap();
+        }
+        let directories = [
+            Directory::new(users.path().to_str().unwrap()).unwrap(),
+            Directory::new(billing.path().to_str().unwrap()).unwrap(),
+        ];
+
+        let files = migration_files(&directories, None).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                users.path().join("001_accounts.sql"),
+                users.path().join("002_users.sql"),
+                billing.path().join("001_invoices.sql"),
+                billing.path().join("003_payments.sql"),
+            ]
+        );
+        assert!(Osprey::validate(&files, ParserConfiguration::new()).is_ok());
+
+        // names are unique across every directory
+        std::fs::write(billing.path().join("002_users.sql"), "-- tag:up\nSELECT 1;").unwrap();
+        let files = migration_files(&directories, None).unwrap();
+        assert!(matches!(
+            Osprey::validate(&files, ParserConfiguration::new()),
+            Err(OspreyError::DuplicateName(name, _)) if name == "002_users"
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let dir = tempfile::tempdir().unwrap();
+        for folder in ["2023", "2024"] {
+            std::fs::create_dir(dir.path().join(folder)).unwrap();
+        }
+        for name in [
+            "001_a.sql",
+            "2023/002_b.sql",
+            "2024/002_b.sql",
+            "2024/003_c.sql",
+        ] {
+            std::fs::write(dir.path().join(name), "-- tag:up\nSELECT 1;").unwrap();
+        }
+        let files = Directory::new(dir.path().to_str().unwrap())
+            .unwrap()
+            .recursive(true)
+            .get_file_list("sql")
+            .unwrap();
+
+        let err = Osprey::validate(&files, ParserConfiguration::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Migration names have to be unique, these files are all named 002_b: {}, {}",
+                dir.path().join("2023/002_b.sql").display(),
+                dir.path().join("2024/002_b.sql").display()
+            )
+        );
+        assert!(Osprey::validate(&files[..2], ParserConfiguration::new()).is_ok());
+    }
+
+    #[test]
+    fn test_validate_reader() {
+        let migration = "-- tag:up\nSELECT 1;\n-- tag:down\nSELECT 2;\n";
+        assert!(Osprey::validate_reader(
+            std::io::Cursor::new(migration),
+            ParserConfiguration::new()
+        )
+        .is_ok());
+        assert!(matches!(
+            Osprey::validate_reader(
+                std::io::Cursor::new("SELECT 1;"),
+                ParserConfiguration::new()
+            ),
+            Err(OspreyError::InvalidFiles(1))
+        ));
+    }
+
+    #[test]
+    fn test_json_reports() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        let mut files = sql_files();
+        let first = files.remove(0);
+        migrate(&mut storage, vec![first.clone()]);
+
+        let mut app_context = app_context(&mut storage, vec![first, files.remove(0)]);
+        let app_arguments = migrate_arguments();
+        let report = Osprey::migrate(&mut app_context, &app_arguments).unwrap();
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "applied": [
+                    {"name": "002_posts", "tag": "up", "query_count": 2, "affected_rows": 0}
+                ],
+                "skipped": ["001_users"],
+                "query_count": 2,
+                "total_affected_rows": 0,
+                "dry_run": false,
+            })
+        );
+
+        app_context.sql_sets.remove(0);
+        let app_arguments = SanityAppArguments {
+            ignore_new_files: false,
+            fail_fast: false,
+            fix: false,
+        };
+        let report = Osprey::sanity(&mut app_context, &app_arguments).unwrap();
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "problems": [{
+                    "kind": "missing_file",
+                    "file": "001_users",
+                    "tag": null,
+                    "message": "The file 001_users does not exist but exists in the migration table",
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_format() {
+        let args = Args::try_parse_from(["osprey", "--format", "json", "sanity"]).unwrap();
+        assert_eq!(args.format, OutputFormat::Json);
+        let args = Args::try_parse_from(["osprey", "sanity"]).unwrap();
+        assert_eq!(args.format, OutputFormat::Text);
+        assert!(Args::try_parse_from(["osprey", "--format", "xml", "sanity"]).is_err());
+    }
+
+    #[test]
+    fn test_log_level() {
+        let level = |args: &[&str]| {
+            let args = Args::try_parse_from(args).unwrap();
+            log_level(args.verbose, args.quiet)
+        };
+        assert_eq!(level(&["osprey", "sanity"]), LevelFilter::Info);
+        assert_eq!(level(&["osprey", "-v", "sanity"]), LevelFilter::Debug);
+        assert_eq!(level(&["osprey", "-vv", "sanity"]), LevelFilter::Trace);
+        assert_eq!(level(&["osprey", "--quiet", "sanity"]), LevelFilter::Warn);
+        assert!(Args::try_parse_from(["osprey", "-v", "-q", "sanity"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_subcommands() {
+        let args = Args::try_parse_from(["osprey", "migrate", "-a", "UP", "--no-lock"]).unwrap();
+        match args.command {
+            Command::Migrate(app_arguments) => {
+                assert_eq!(app_arguments.up_keys, vec!["up"]);
+                assert!(!app_arguments.lock);
+                assert!(!app_arguments.dry_run);
+                assert!(!app_arguments.strict_versions);
+            }
+            command => panic!("unexpected command {:?}", command),
+        }
+
+        let args =
+            Args::try_parse_from(["osprey", "migrate", "--tag", "up", "--tag", "Seed,fixtures"])
+                .unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Migrate(MigrateAppArguments { up_keys, .. })
+                if up_keys == vec!["up", "seed", "fixtures"]
+        ));
+
+        let args = Args::try_parse_from(["osprey", "-m", "db", "status"]).unwrap();
+        assert_eq!(args.migrations_directories, vec!["db"]);
+        assert!(args.create_table);
+
+        let args = Args::try_parse_from(["osprey", "--no-create-table", "migrate"]).unwrap();
+        assert!(!args.create_table);
+
+        let args =
+            Args::try_parse_from(["osprey", "-m", "users", "-m", "billing,shared", "status"])
+                .unwrap();
+        assert_eq!(
+            args.migrations_directories,
+            vec!["users", "billing", "shared"]
+        );
+        assert!(matches!(
+            args.command,
+            Command::Status(StatusAppArguments { up_key }) if up_key == "up"
+        ));
+
+        let args = Args::try_parse_from(["osprey", "baseline", "--files", "001_a,002_b"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Baseline(BaselineAppArguments { files, .. }) if files == vec!["001_a", "002_b"]
+        ));
+
+        let args =
+            Args::try_parse_from(["osprey", "rollback", "--down-tag", "Revert", "--steps", "2"])
+                .unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Rollback(RollbackAppArguments { up_key, down_key, steps: 2 })
+                if up_key == "up" && down_key == "revert"
+        ));
+
+        let args = Args::try_parse_from(["osprey", "pending", "--exit-on-pending"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Pending(PendingAppArguments { up_key, exit_on_pending: true }) if up_key == "up"
+        ));
+
+        let args = Args::try_parse_from(["osprey", "redo", "-a", "Next"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Redo(RedoAppArguments { up_key, down_key })
+                if up_key == "next" && down_key == "down"
+        ));
+
+        let args = Args::try_parse_from(["osprey", "sanity", "-i"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Sanity(SanityAppArguments {
+                ignore_new_files: true,
+                fail_fast: false,
+                fix: false
+            })
+        ));
+
+        let args = Args::try_parse_from(["osprey", "forget", "001_users", "Seed"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Forget(ForgetAppArguments { name, tag, yes: false })
+                if name == "001_users" && tag == "seed"
+        ));
+
+        let args = Args::try_parse_from(["osprey", "force-unlock", "--yes"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::ForceUnlock(ForceUnlockAppArguments { yes: true })
+        ));
+
+        let args = Args::try_parse_from(["osprey", "drop", "--yes"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Drop(DropAppArguments { yes: true })
+        ));
+
+        let args = Args::try_parse_from(["osprey", "reset", "-a", "Schema"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Reset(ResetAppArguments { up_key, yes: false, .. }) if up_key == "schema"
+        ));
+
+        let args = Args::try_parse_from(["osprey", "validate"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Validate(ValidateAppArguments { file: None })
+        ));
+
+        let args = Args::try_parse_from(["osprey", "validate", "-"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Validate(ValidateAppArguments { file: Some(file) }) if file == "-"
+        ));
+
+        let args = Args::try_parse_from(["osprey", "ping"]).unwrap();
+        assert!(matches!(args.command, Command::Ping));
+
+        let args = Args::try_parse_from(["osprey", "create", "add_users"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::New(NewAppArguments { name, .. }) if name == "add_users"
+        ));
+
+        assert!(Args::try_parse_from(["osprey", "migrat"]).is_err());
+        assert!(Args::try_parse_from(["osprey"]).is_err());
+    }
+
+    #[test]
+    fn test_apply_config() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            file.path(),
+            "migrations_directory = [\"users\", \"billing\"]\nmigrations_table = \"schema_history\"\ntag = \"Seed\"\n\n[database]\nhost = \"db\"\n",
+        )
+        .unwrap();
+        let config = file.path().to_str().unwrap();
+        let parse = |command_line: &[&str]| {
+            let matches = Args::into_app().try_get_matches_from(command_line).unwrap();
+            let mut args = Args::from_arg_matches(&matches).unwrap();
+            apply_config_file(&mut args, &matches).unwrap();
+            args
+        };
+
+        let args = parse(&["osprey", "--config", config, "migrate"]);
+        assert_eq!(args.migrations_directories, vec!["users", "billing"]);
+        assert_eq!(args.migrations_table, "schema_history");
+        assert_eq!(args.database.host.as_deref(), Some("db"));
+        assert!(matches!(
+            args.command,
+            Command::Migrate(MigrateAppArguments { up_keys, .. }) if up_keys == vec!["seed"]
+        ));
+
+        // flags on the command line win over the file
+        let args = parse(&[
+            "osprey",
+            "--config",
+            config,
+            "-m",
+            "db",
+            "-t",
+            "_migrations",
+            "status",
+            "-a",
+            "up",
+        ]);
+        assert_eq!(args.migrations_directories, vec!["db"]);
+        assert_eq!(args.migrations_table, "_migrations");
+        assert!(matches!(
+            args.command,
+            Command::Status(StatusAppArguments { up_key }) if up_key == "up"
+        ));
+
+        // commands without a tag ignore it
+        let args = parse(&["osprey", "--config", config, "history"]);
+        assert!(matches!(args.command, Command::History));
+
+        let matches = Args::into_app()
+            .try_get_matches_from(["osprey", "--config", "/does/not/exist.toml", "history"])
+            .unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        assert!(matches!(
+            apply_config_file(&mut args, &matches),
+            Err(OspreyError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_migrate_to_target() {
+        let configuration = ParserConfiguration::new();
+        let files: Vec<_> = ["005_e", "001_a", "003_add_index", "002_b", "004_d"]
+            .iter()
+            .map(|name| {
+                SQLFile::new_from_string(name, "-- tag:up\nSELECT 1;", &configuration).unwrap()
+            })
+            .collect();
+        let migrate_to = |storage: &mut InMemoryMigrationRecordStorage, target: &str| {
+            let mut app_context = app_context(storage, files.clone());
+            let app_arguments = MigrateAppArguments {
+                target: Some(target.to_string()),
+                ..migrate_arguments()
+            };
+            Osprey::migrate(&mut app_context, &app_arguments)
+        };
+
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        let report = migrate_to(&mut storage, "003_add_index.sql").unwrap();
+        assert_eq!(report.applied.len(), 3);
+        assert!(report.skipped.is_empty());
+        let names: Vec<_> = storage.records.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["001_a", "002_b", "003_add_index"]);
+
+        assert!(matches!(
+            migrate_to(&mut storage, "2"),
+            Err(OspreyError::TargetMigrated(target)) if target == "2"
+        ));
+        assert!(matches!(
+            migrate_to(&mut storage, "006_f"),
+            Err(OspreyError::UnknownTarget(target)) if target == "006_f"
+        ));
+        assert_eq!(storage.records.len(), 3);
+
+        let report = migrate_to(&mut storage, "4").unwrap();
+        assert_eq!(storage.records.len(), 4);
+        // 005_e comes after the target, it's still pending
+        assert_eq!(report.skipped, vec!["001_a", "003_add_index", "002_b"]);
+    }
+
+    #[test]
+    fn test_migrate_in_version_order() {
+        let configuration = ParserConfiguration::new();
+        let files: Vec<_> = ["10_c", "notes", "9_b", "V1__a"]
+            .iter()
+            .map(|name| {
+                SQLFile::new_from_string(name, "-- tag:up\nSELECT 1;", &configuration).unwrap()
+            })
+            .collect();
+
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, files);
+
+        let names: Vec<_> = storage.records.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["V1__a", "9_b", "10_c", "notes"]);
+    }
+
+    #[test]
+    fn test_strict_versions() {
+        let configuration = ParserConfiguration::new();
+        let files: Vec<_> = ["001_a", "003_c"]
+            .iter()
+            .map(|name| {
+                SQLFile::new_from_string(name, "-- tag:up\nSELECT 1;", &configuration).unwrap()
+            })
+            .collect();
+
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        let mut app_context = app_context(&mut storage, files);
+        let mut app_arguments = MigrateAppArguments {
+            strict_versions: true,
+            ..migrate_arguments()
+        };
+        assert!(matches!(
+            Osprey::migrate(&mut app_context, &app_arguments),
+            Err(OspreyError::InvalidVersions(1))
+        ));
+
+        // without the flag the gap is only a warning
+        app_arguments.strict_versions = false;
+        Osprey::migrate(&mut app_context, &app_arguments).unwrap();
+        assert_eq!(storage.records.len(), 2);
+    }
+
+    #[test]
+    fn test_dry_run_writes_nothing() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        let mut app_context = app_context(&mut storage, sql_files());
+        let app_arguments = MigrateAppArguments {
+            dry_run: true,
+            ..migrate_arguments()
+        };
+        Osprey::migrate(&mut app_context, &app_arguments).unwrap();
+
+        assert!(storage.records.is_empty());
+        assert!(storage.executed_queries.is_empty());
+    }
+
+    #[test]
+    fn test_migrate_steps() {
+        let configuration = ParserConfiguration::new();
+        let files: Vec<_> = ["001_a", "002_b", "003_c", "004_d"]
+            .iter()
+            .map(|name| {
+                SQLFile::new_from_string(name, "-- tag:up\nSELECT 1;", &configuration).unwrap()
+            })
+            .collect();
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        let mut app_context = app_context(&mut storage, files);
+        let app_arguments = MigrateAppArguments {
+            steps: Some(2),
+            ..migrate_arguments()
+        };
+        Osprey::migrate(&mut app_context, &app_arguments).unwrap();
+
+        let names: Vec<_> = storage.records.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["001_a", "002_b"]);
+    }
+
+    #[test]
+    fn test_parse_steps() {
+        assert_eq!(parse_steps("2"), Ok(2));
+        assert!(parse_steps("0").is_err());
+        assert!(parse_steps("-1").is_err());
+        assert!(Args::try_parse_from(["osprey", "migrate", "--steps", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_pending_migrations() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        let files = sql_files();
+        baseline(&mut storage, sql_files(), &["001_users"]).unwrap();
+
+        let pending: Vec<_> = migrations::pending_migrations(&storage.records, &files, "up")
+            .into_iter()
+            .map(|(file, query_set)| (file.name.as_str(), query_set.queries.len()))
+            .collect();
+        assert_eq!(pending, vec![("002_posts", 2)]);
+
+        let pending = migrations::pending_migrations(&storage.records, &files, "down");
+        let names: Vec<_> = pending.iter().map(|(file, _)| file.name.as_str()).collect();
+        assert_eq!(names, vec!["001_users", "002_posts"]);
+    }
+
+    #[test]
+    fn test_migration_status() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files());
+        storage.records.push(MigrationInstance::new(
+            3,
+            "000_removed",
+            "up",
+            "HASH_REMOVED",
+        ));
+
+        let mut files = sql_files();
+        let users_hash = files[0].get_query_set("up").unwrap().hash.clone();
+        let posts_hash = files[1].get_query_set("up").unwrap().hash.clone();
+        files[1] = SQLFile::new_from_string(
+            "002_posts",
+            "-- tag:up\nCREATE TABLE posts (id BIGINT);",
+            &ParserConfiguration::new(),
+        )
+        .unwrap();
+        let comments = SQLFile::new_from_string(
+            "003_comments",
+            "-- tag:up\nCREATE TABLE comments (id INT);",
+            &ParserConfiguration::new(),
+        )
+        .unwrap();
+        let comments_hash = comments.get_query_set("up").unwrap().hash.clone();
+        // files are listed by name, not in the order they're given
+        files.insert(0, comments);
+        files.push(
+            SQLFile::new_from_string(
+                "004_seed",
+                "-- tag:seed\nINSERT INTO users VALUES (1);",
+                &ParserConfiguration::new(),
+            )
+            .unwrap(),
+        );
+
+        let statuses: Vec<_> = Osprey::migration_status(&storage.records, &files, "up")
+            .into_iter()
+            .map(|s| (s.name, s.state, s.hash))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (
+                    "000_removed".to_string(),
+                    MigrationState::NoFile,
+                    Some("HASH_REMOVED".to_string())
+                ),
+                (
+                    "001_users".to_string(),
+                    MigrationState::Applied,
+                    Some(users_hash)
+                ),
+                (
+                    "002_posts".to_string(),
+                    MigrationState::Changed,
+                    Some(posts_hash)
+                ),
+                (
+                    "003_comments".to_string(),
+                    MigrationState::Pending,
+                    Some(comments_hash)
+                ),
+                ("004_seed".to_string(), MigrationState::NoTag, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rollback_two_steps() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files());
+        storage.executed_queries.clear();
+
+        rollback(&mut storage, sql_files(), 2).unwrap();
+
+        assert!(storage.records.is_empty());
+        assert_eq!(
+            storage.executed_queries,
+            vec!["DROP TABLE posts;", "DROP TABLE users;"]
+        );
+
+        // rolled back files migrate again
+        migrate(&mut storage, sql_files());
+        assert_eq!(storage.records.len(), 2);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files());
+        storage
+            .add_record("001_users", "seed", "HASH", None)
+            .unwrap();
+        storage.executed_queries.clear();
+
+        let mut app_context = app_context(&mut storage, sql_files());
+        let app_arguments = ResetAppArguments {
+            up_key: "up".to_string(),
+            down_key: "down".to_string(),
+            yes: true,
+        };
+        Osprey::reset(&mut app_context, &app_arguments).unwrap();
+
+        // records of other tags are forgotten without being rolled back
+        assert!(storage.records.is_empty());
+        assert_eq!(
+            storage.executed_queries,
+            vec!["DROP TABLE posts;", "DROP TABLE users;"]
+        );
+    }
+
+    fn redo(
+        storage: &mut InMemoryMigrationRecordStorage,
+        sql_sets: Vec<SQLFile>,
+    ) -> Result<(), OspreyError> {
+        let mut app_context = app_context(storage, sql_sets);
+        let app_arguments = RedoAppArguments {
+            up_key: "up".to_string(),
+            down_key: "down".to_string(),
+        };
+        Osprey::redo(&mut app_context, &app_arguments)
+    }
+
+    #[test]
+    fn test_redo() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        assert!(matches!(
+            redo(&mut storage, sql_files()),
+            Err(OspreyError::NothingApplied(tag)) if tag == "up"
+        ));
+
+        migrate(&mut storage, sql_files());
+        storage.executed_queries.clear();
+
+        // the newest migration was edited since it was applied
+        let mut files = sql_files();
+        files[1] = SQLFile::new_from_string(
+            "002_posts",
+            "-- tag:up\nCREATE TABLE posts (id BIGINT);\n-- tag:down\nDROP TABLE posts;",
+            &ParserConfiguration::new(),
+        )
+        .unwrap();
+        redo(&mut storage, files.clone()).unwrap();
+
+        assert_eq!(
+            storage.executed_queries,
+            vec!["DROP TABLE posts;", "CREATE TABLE posts (id BIGINT);"]
+        );
+        let names: Vec<_> = storage.records.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["001_users", "002_posts"]);
+        assert_eq!(
+            storage.records[1].hash,
+            files[1].get_query_set("up").unwrap().hash
+        );
+    }
+
+    #[test]
+    fn test_rollback_and_redo_with_hooks() {
+        let mut files = sql_files();
+        files.push(
+            SQLFile::new_from_string(
+                "hooks",
+                "-- tag:before_each\nSET ROLE app;\n-- tag:after_each\nRESET ROLE;",
+                &ParserConfiguration::new(),
+            )
+            .unwrap(),
+        );
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, files.clone());
+        storage.executed_queries.clear();
+
+        // down query sets are wrapped the same way as up ones
+        redo(&mut storage, files.clone()).unwrap();
+        rollback(&mut storage, files, 1).unwrap();
+        assert_eq!(
+            storage.executed_queries,
+            vec![
+                "SET ROLE app;",
+                "DROP TABLE posts;",
+                "RESET ROLE;",
+                "SET ROLE app;",
+                "CREATE TABLE posts (id INT);",
+                "CREATE INDEX posts_id ON posts (id);",
+                "RESET ROLE;",
+                "SET ROLE app;",
+                "DROP TABLE posts;",
+                "RESET ROLE;",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redo_missing_down_tag() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        let files = vec![SQLFile::new_from_string(
+            "001_users",
+            "-- tag:up\nCREATE TABLE users (id INT);",
+            &ParserConfiguration::new(),
+        )
+        .unwrap()];
+        migrate(&mut storage, files.clone());
+        storage.executed_queries.clear();
+
+        assert!(matches!(
+            redo(&mut storage, files),
+            Err(OspreyError::MissingTag(name, tag)) if name == "001_users" && tag == "down"
+        ));
+        assert!(storage.executed_queries.is_empty());
+        assert_eq!(storage.records.len(), 1);
+    }
+
+    #[test]
+    fn test_rollback_one_step() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files());
+
+        rollback(&mut storage, sql_files(), 1).unwrap();
+
+        assert_eq!(storage.records.len(), 1);
+        assert_eq!(storage.records[0].name, "001_users");
+    }
+
+    #[test]
+    fn test_rollback_missing_down_tag() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        let files = vec![SQLFile::new_from_string(
+            "001_users",
+            "-- tag:up\nCREATE TABLE users (id INT);",
+            &ParserConfiguration::new(),
+        )
+        .unwrap()];
+        migrate(&mut storage, files.clone());
+        storage.executed_queries.clear();
+
+        assert!(matches!(
+            rollback(&mut storage, files, 1),
+            Err(OspreyError::MissingTag(file, tag)) if file == "001_users" && tag == "down"
+        ));
+        assert_eq!(storage.records.len(), 1);
+        assert!(storage.executed_queries.is_empty());
+    }
+
+    #[test]
+    fn test_rollback_missing_file() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files());
+        storage.executed_queries.clear();
+
+        let mut files = sql_files();
+        files.remove(0);
+        assert!(matches!(
+            rollback(&mut storage, files, 2),
+            Err(OspreyError::MissingFile(name)) if name == "001_users"
+        ));
+        assert_eq!(storage.records.len(), 2);
+        assert!(storage.executed_queries.is_empty());
+    }
+
+    #[test]
+    fn test_sanity_after_migrate() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        match sanity(&mut storage, sql_files()) {
+            Err(OspreyError::SanityErrors(errors)) => {
+                assert_eq!(errors.len(), 2);
+                assert!(errors
+                    .iter()
+                    .all(|err| matches!(err, SanityError::NotMigrated(_))));
+            }
+            result => panic!("expected every file to be reported, got {:?}", result),
+        }
+
+        migrate(&mut storage, sql_files());
+        assert!(sanity(&mut storage, sql_files()).is_ok());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_migrate_and_sanity() {
+        let mut client = SqliteClient::open_in_memory().unwrap();
+        let files = sql_files();
+        let users_hash = files[0].get_query_set("up").unwrap().hash.clone();
+        {
+            let mut storage =
+                DatabaseMigrationRecordStorage::new("_migrations", &mut client).unwrap();
+            let mut app_context = app_context(&mut storage, sql_files());
+            let migrate_arguments = migrate_arguments();
+            Osprey::migrate(&mut app_context, &migrate_arguments).unwrap();
+            // running again doesn't re-apply anything
+            Osprey::migrate(&mut app_context, &migrate_arguments).unwrap();
+
+            let sanity_arguments = SanityAppArguments {
+                ignore_new_files: false,
+                fail_fast: false,
+                fix: false,
+            };
+            Osprey::sanity(&mut app_context, &sanity_arguments)
+                .unwrap()
+                .into_result()
+                .unwrap();
+
+            let records = Migrations::new(&mut storage)
+                .unwrap()
+                .get_migrations()
+                .unwrap();
+            assert_eq!(records.len(), 2);
+            assert!(records[0].duration_ms.is_some());
+            assert!(records[0].applied_date.is_some());
+            assert_eq!(
+                MigrationInstance {
+                    duration_ms: None,
+                    applied_date: None,
+                    ..records[0].clone()
+                },
+                MigrationInstance::new(1, "001_users", "up", &users_hash)
+            );
+        }
+
+        let tables = client
+            .query_row(
+                "SELECT name FROM sqlite_master WHERE type = 'table' AND name IN ('users', 'posts') ORDER BY name",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(tables, vec![vec!["posts".into()], vec!["users".into()]]);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_failed_migration_rolls_back() {
+        let mut client = SqliteClient::open_in_memory().unwrap();
+        {
+            let mut storage =
+                DatabaseMigrationRecordStorage::new("_migrations", &mut client).unwrap();
+            let files = vec![SQLFile::new_from_string(
+                "001_broken",
+                "-- tag:up\nCREATE TABLE a (id INT);\nINSERT INTO missing VALUES (1);\nCREATE TABLE b (id INT);",
+                &ParserConfiguration::new(),
+            )
+            .unwrap()];
+            let mut app_context = app_context(&mut storage, files);
+            let migrate_arguments = migrate_arguments();
+            assert!(Osprey::migrate(&mut app_context, &migrate_arguments).is_err());
+
+            let records = Migrations::new(&mut storage)
+                .unwrap()
+                .get_migrations()
+                .unwrap();
+            assert!(records.is_empty());
+        }
+
+        let tables = client
+            .query_row("SELECT name FROM sqlite_master WHERE name = 'a'", &[])
+            .unwrap();
+        assert!(tables.is_empty());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_sanity_detects_changed_file() {
+        let mut client = SqliteClient::open_in_memory().unwrap();
+        let mut storage = DatabaseMigrationRecordStorage::new("_migrations", &mut client).unwrap();
+        let mut app_context = app_context(&mut storage, sql_files());
+        let migrate_arguments = migrate_arguments();
+        Osprey::migrate(&mut app_context, &migrate_arguments).unwrap();
+
+        app_context.sql_sets[0] = SQLFile::new_from_string(
+            "001_users",
+            "-- tag:up\nCREATE TABLE users (id BIGINT);",
+            &ParserConfiguration::new(),
+        )
+        .unwrap();
+        let sanity_arguments = SanityAppArguments {
+            ignore_new_files: false,
+            fail_fast: false,
+            fix: false,
+        };
+        assert!(matches!(
+            Osprey::sanity(&mut app_context, &sanity_arguments).and_then(SanityReport::into_result),
+            Err(OspreyError::Sanity(SanityError::QuerySetChanged(name, _))) if name == "001_users"
+        ));
+    }
+
+    #[test]
+    fn test_sanity_detects_changed_file() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files());
+
+        let mut files = sql_files();
+        files[1] = SQLFile::new_from_string(
+            "002_posts",
+            "-- tag:up\nCREATE TABLE posts (id BIGINT);",
+            &ParserConfiguration::new(),
+        )
+        .unwrap();
+        assert!(matches!(
+            sanity(&mut storage, files),
+            Err(OspreyError::Sanity(SanityError::QuerySetChanged(name, _))) if name == "002_posts"
+        ));
+    }
+
+    #[test]
+    fn test_migrate_refuses_changed_file() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files()[..1].to_vec());
+
+        let mut files = sql_files();
+        files[0] = SQLFile::new_from_string(
+            "001_users",
+            "-- tag:up\nCREATE TABLE users (id BIGINT);\n-- tag:down\nDROP TABLE users;",
+            &ParserConfiguration::new(),
+        )
+        .unwrap();
+        let mut app_context = app_context(&mut storage, files);
+        let mut app_arguments = migrate_arguments();
+        assert!(matches!(
+            Osprey::migrate(&mut app_context, &app_arguments),
+            Err(OspreyError::ChangedQuerySets(problems)) if matches!(
+                &problems[..],
+                [SanityError::QuerySetChanged(name, tag)] if name == "001_users" && tag == "up"
+            )
+        ));
+        assert_eq!(
+            app_context.record_storage.get_all_records().unwrap().len(),
+            1
+        );
+
+        app_arguments.allow_changed = true;
+        let report = Osprey::migrate(&mut app_context, &app_arguments).unwrap();
+        assert_eq!(report.applied.len(), 1);
+        assert_eq!(report.applied[0].name, "002_posts");
+    }
+
+    #[test]
+    fn test_sanity_reports_every_problem() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files());
+        storage
+            .records
+            .push(MigrationInstance::new(3, "002_posts", "down", "HASH"));
+
+        // the up query set changed and the down tag that was migrated is gone
+        let file = SQLFile::new_from_string(
+            "002_posts",
+            "-- tag:up\nCREATE TABLE posts (id BIGINT);",
+            &ParserConfiguration::new(),
+        )
+        .unwrap();
+        let files = vec![sql_files().remove(0), file];
+
+        let problems = Osprey::instance_file_check(&storage.records, &files, false, false);
+        let problems: Vec<_> = problems.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            problems,
+            vec![
+                SanityError::QuerySetChanged("002_posts".to_string(), "up".to_string()).to_string(),
+                SanityError::NoContainTag("002_posts".to_string(), "down".to_string()).to_string(),
+            ]
+        );
+        assert!(matches!(
+            sanity(&mut storage, files.clone()),
+            Err(OspreyError::SanityErrors(errors)) if errors.len() == 2
+        ));
+
+        let problems = Osprey::instance_file_check(&storage.records, &files, false, true);
+        assert_eq!(problems.len(), 1);
+    }
+
+    #[test]
+    fn test_sanity_with_another_checksum_algorithm() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files());
+
+        let configuration =
+            ParserConfiguration::new().checksum_algorithm(ChecksumAlgorithm::Blake3);
+        let mut files = vec![
+            SQLFile::new_from_string(
+                "001_users",
+                "-- tag:up\nCREATE TABLE users (id INT);\n-- tag:down\nDROP TABLE users;",
+                &configuration,
+            )
+            .unwrap(),
+            SQLFile::new_from_string(
+                "002_posts",
+                "-- tag:up\nCREATE TABLE posts (id BIGINT);",
+                &configuration,
+            )
+            .unwrap(),
+        ];
+        assert!(files[0]
+            .get_query_set("up")
+            .unwrap()
+            .hash
+            .starts_with("blake3:"));
+
+        // the sha256 record of the unchanged file still matches, the changed one doesn't
+        let problems = Osprey::instance_file_check(&storage.records, &files, false, false);
+        assert!(matches!(
+            &problems[..],
+            [SanityError::QuerySetChanged(name, tag)] if name == "002_posts" && tag == "up"
+        ));
+
+        files.truncate(1);
+        files.push(sql_files().remove(1));
+        assert!(Osprey::instance_file_check(&storage.records, &files, false, false).is_empty());
+    }
+
+    #[test]
+    fn test_sanity_out_of_order() {
+        let configuration = ParserConfiguration::new();
+        let files: Vec<_> = ["001_a", "002_b", "003_c", "004_d"]
+            .iter()
+            .map(|name| {
+                SQLFile::new_from_string(name, "-- tag:up\nSELECT 1;", &configuration).unwrap()
+            })
+            .collect();
+        let hash = files[0].get_query_set("up").unwrap().hash.clone();
+
+        // 003 was merged and migrated before 002, the seed tag is ordered on its own
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        for (name, tag) in [
+            ("001_a", "up"),
+            ("003_c", "up"),
+            ("002_b", "up"),
+            ("002_b", "seed"),
+            ("004_d", "up"),
+            ("001_a", "seed"),
+        ] {
+            storage.add_record(name, tag, &hash, None).unwrap();
+        }
+
+        let problems = Osprey::instance_file_check(&storage.records, &files, true, false);
+        let out_of_order: Vec<_> = problems
+            .iter()
+            .filter_map(|problem| match problem {
+                SanityError::OutOfOrder(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(out_of_order, vec!["002_b", "001_a"]);
+
+        storage.records.retain(|record| record.tag == "up");
+        storage.records.swap(1, 2);
+        for (index, record) in storage.records.iter_mut().enumerate() {
+            record.index = index as i32 + 1;
+        }
+        assert!(Osprey::instance_file_check(&storage.records, &files, true, false).is_empty());
+    }
+
+    #[test]
+    fn test_sanity_fix() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, sql_files());
+
+        let mut files = sql_files();
+        files[1] = SQLFile::new_from_string(
+            "002_posts",
+            "-- tag:up\nCREATE   TABLE posts (id BIGINT);\n-- tag:down\nDROP TABLE posts;",
+            &ParserConfiguration::new(),
+        )
+        .unwrap();
+        let new_hash = files[1].get_query_set("up").unwrap().hash.clone();
+
+        let mut app_context = app_context(&mut storage, files.clone());
+        let app_arguments = SanityAppArguments {
+            ignore_new_files: false,
+            fail_fast: false,
+            fix: true,
+        };
+        let report = Osprey::sanity(&mut app_context, &app_arguments).unwrap();
+        assert!(report.problems.is_empty());
+
+        let record = storage
+            .records
+            .iter()
+            .find(|r| r.name == "002_posts" && r.tag == "up")
+            .unwrap();
+        assert_eq!(record.hash, new_hash);
+        assert!(sanity(&mut storage, files).is_ok());
+    }
+
+    #[test]
+    fn test_pending() {
+        let configuration = ParserConfiguration::new();
+        let mut files = sql_files();
+        files.insert(
+            0,
+            SQLFile::new_from_string(
+                "V3__comments",
+                "-- tag:up\nCREATE TABLE comments (id INT);",
+                &configuration,
+            )
+            .unwrap(),
+        );
+        files.push(
+            SQLFile::new_from_string("seed", "-- tag:seed\nSELECT 1;", &configuration).unwrap(),
+        );
+
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        migrate(&mut storage, vec![files[1].clone()]);
+        let records = Migrations::new(&mut storage)
+            .unwrap()
+            .get_migrations()
+            .unwrap();
+        assert_eq!(
+            Osprey::pending_names(&records, &files, "up"),
+            vec!["002_posts", "V3__comments"]
+        );
+        assert_eq!(
+            Osprey::pending_names(&records, &files, "seed"),
+            vec!["seed"]
+        );
+
+        let mut app_context = app_context(&mut storage, files);
+        let app_arguments = PendingAppArguments {
+            up_key: "up".to_string(),
+            exit_on_pending: true,
+        };
+        assert!(matches!(
+            Osprey::pending(&mut app_context, &app_arguments),
+            Err(OspreyError::PendingMigrations(2))
+        ));
+        let app_arguments = PendingAppArguments {
+            up_key: "up".to_string(),
+            exit_on_pending: false,
+        };
+        assert!(Osprey::pending(&mut app_context, &app_arguments).is_ok());
+    }
+
+    #[test]
+    fn test_history_lines() {
+        let mut storage = InMemoryMigrationRecordStorage::new();
+        storage.records = vec![
+            MigrationInstance {
+                applied_date: Some("2024-01-16".to_string()),
+                ..MigrationInstance::new(12, "002_posts", "up", "ABCDEF0123456789")
+            },
+            MigrationInstance {
+                applied_date: Some("2024-01-15".to_string()),
+                ..MigrationInstance::new(3, "001_users", "up", "0123456789ABCDEF")
+            },
+            MigrationInstance::new(14, "001_users", "seed", "SHORT"),
+        ];
+
+        let records = Migrations::new(&mut storage)
+            .unwrap()
+            .get_migrations()
+            .unwrap();
+        assert_eq!(
+            Osprey::history_lines(records),
+            vec![
+                "   3  001_users  up    2024-01-15  0123456789AB",
+                "  12  002_posts  up    2024-01-16  ABCDEF012345",
+                "  14  001_users  seed  -           SHORT",
+            ]
+        );
+    }
+}
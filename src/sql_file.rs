@@ -1,12 +1,69 @@
 use crate::error::SQLFileError;
+use indexmap::IndexMap;
+use rayon::prelude::*;
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
 use std::fmt;
-use std::fs;
-use std::path::Path;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::str;
+use std::str::FromStr;
+
+const DEFAULT_TAG_PREFIX: &str = "tag:";
+const DEFAULT_DELIMITER: &str = ";";
+const BLAKE3_PREFIX: &str = "blake3:";
+// the keys of "-- key: value" header comments read as metadata, other comments like
+// "-- TODO: ..." stay ordinary comments
+pub const METADATA_KEYS: [&str; 2] = ["description", "author"];
+
+// ChecksumAlgorithm selects how query sets are hashed
+//   Sha256 - the default, its hashes are plain hex so records made before the algorithm could
+//   be chosen keep matching
+//   Blake3 - faster for large query sets, its hashes start with blake3: so every recorded
+//   hash tells which algorithm made it
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ChecksumAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            "blake3" => Ok(ChecksumAlgorithm::Blake3),
+            _ => Err(format!(
+                "Unknown checksum algorithm {}, expected sha256 or blake3",
+                s
+            )),
+        }
+    }
+}
+
+impl ChecksumAlgorithm {
+    pub fn checksum(self, text: &str) -> String {
+        match self {
+            ChecksumAlgorithm::Sha256 => format!("{:X}", Sha256::digest(text.as_bytes())),
+            ChecksumAlgorithm::Blake3 => format!(
+                "{}{}",
+                BLAKE3_PREFIX,
+                blake3::hash(text.as_bytes()).to_hex().to_uppercase()
+            ),
+        }
+    }
 
-const TAG_LINE: &str = "tag:";
+    // the algorithm a recorded hash was made with
+    pub fn of_hash(hash: &str) -> ChecksumAlgorithm {
+        if hash.starts_with(BLAKE3_PREFIX) {
+            ChecksumAlgorithm::Blake3
+        } else {
+            ChecksumAlgorithm::Sha256
+        }
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub enum SyntaxErrorMessage {
@@ -14,9 +71,13 @@ pub enum SyntaxErrorMessage {
     TagNameIncompleteQuery,
     NoQueryForTag,
     CouldNotParseTagName,
-    CommentInQuery,
     EOFIncompleteQuery,
+    EOFUnclosedComment,
     NoQueriesFound,
+    DuplicateTag(String),
+    UnknownTagOption(String),
+    QueryForNoopTag,
+    InvalidTagName(String),
 }
 
 impl fmt::Display for SyntaxErrorMessage {
@@ -26,49 +87,227 @@ impl fmt::Display for SyntaxErrorMessage {
             SyntaxErrorMessage::TagNameIncompleteQuery => {
                 write!(f, "Tag name defined without completing previous query")
             }
-            SyntaxErrorMessage::NoQueryForTag => write!(f, "No query given for tag"),
+            SyntaxErrorMessage::NoQueryForTag => write!(
+                f,
+                "No query given for tag, declare it (noop) if it's meant to do nothing"
+            ),
             SyntaxErrorMessage::CouldNotParseTagName => write!(f, "Could not parse tag name"),
-            SyntaxErrorMessage::CommentInQuery => write!(f, "Comment found while defining query"),
             SyntaxErrorMessage::EOFIncompleteQuery => {
                 write!(f, "End of file found: unfinished query")
             }
-            SyntaxErrorMessage::NoQueriesFound => write!(f, "No queries found"),
+            SyntaxErrorMessage::EOFUnclosedComment => {
+                write!(f, "End of file found: unclosed block comment")
+            }
+            SyntaxErrorMessage::NoQueriesFound => {
+                write!(
+                    f,
+                    "No queries found, the file is empty or only has comments"
+                )
+            }
+            SyntaxErrorMessage::DuplicateTag(tag) => {
+                write!(f, "Tag {} is defined more than once", tag)
+            }
+            SyntaxErrorMessage::UnknownTagOption(option) => {
+                write!(f, "Unknown tag option {}", option)
+            }
+            SyntaxErrorMessage::QueryForNoopTag => write!(f, "Query given for a noop tag"),
+            SyntaxErrorMessage::InvalidTagName(tag) => write!(
+                f,
+                "Tag name {} may only contain letters, numbers, _ and -",
+                tag
+            ),
         }
     }
 }
 
 impl SyntaxErrorMessage {
-    fn to_err<T>(&self, line_number: i32) -> Result<T, SQLFileError> {
-        Err(SQLFileError::SyntaxError(line_number, format!("{}", &self)))
+    fn to_error(&self, line_number: i32) -> SQLFileError {
+        SQLFileError::SyntaxError(line_number, format!("{}", &self))
     }
 }
 pub type SQLFileResult<T> = Result<T, SQLFileError>;
 pub type Tag = String;
 
-// SQLFile holds queries read from an sql file
-//   the queries are grouped by the tag they are given in the file
+// ParserConfiguration holds the options that change how sql files are parsed
+//   tag_prefix is what follows the -- of a comment line to mark it as a tag, "tag:" by default
+//   delimiter ends a query, ";" by default. Any other delimiter (like GO) has to be on a line of
+//   its own and isn't part of the query
+//   normalize_whitespace hashes queries with every run of whitespace collapsed to a single space
+//   and leading/trailing whitespace removed, so reformatting a query set doesn't change its hash.
+//   This also collapses whitespace inside string literals. Off by default, hashes are byte exact
+//   checksum_algorithm is the algorithm query sets are hashed with, sha256 by default
+//   template_suffix is stripped from file names before the .sql extension, so 001_init.sql.j2
+//   is named 001_init with a template suffix of .j2. None by default
+//   lenient keeps parsing after a syntax error and reports every error of the file together as
+//   SQLFileError::SyntaxErrors. Off by default, parsing stops at the first error
 #[derive(Debug)]
+pub struct ParserConfiguration {
+    tag_prefix: String,
+    delimiter: String,
+    normalize_whitespace: bool,
+    checksum_algorithm: ChecksumAlgorithm,
+    template_suffix: Option<String>,
+    lenient: bool,
+}
+
+impl Default for ParserConfiguration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParserConfiguration {
+    pub fn new() -> ParserConfiguration {
+        ParserConfiguration {
+            tag_prefix: DEFAULT_TAG_PREFIX.to_string(),
+            delimiter: DEFAULT_DELIMITER.to_string(),
+            normalize_whitespace: false,
+            checksum_algorithm: ChecksumAlgorithm::default(),
+            template_suffix: None,
+            lenient: false,
+        }
+    }
+
+    pub fn tag_prefix(mut self, tag_prefix: String) -> ParserConfiguration {
+        self.tag_prefix = tag_prefix;
+        self
+    }
+
+    pub fn delimiter(mut self, delimiter: String) -> ParserConfiguration {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn normalize_whitespace(mut self, normalize_whitespace: bool) -> ParserConfiguration {
+        self.normalize_whitespace = normalize_whitespace;
+        self
+    }
+
+    pub fn checksum_algorithm(
+        mut self,
+        checksum_algorithm: ChecksumAlgorithm,
+    ) -> ParserConfiguration {
+        self.checksum_algorithm = checksum_algorithm;
+        self
+    }
+
+    pub fn template_suffix(mut self, template_suffix: String) -> ParserConfiguration {
+        self.template_suffix = Some(template_suffix);
+        self
+    }
+
+    pub fn lenient(mut self, lenient: bool) -> ParserConfiguration {
+        self.lenient = lenient;
+        self
+    }
+
+    fn is_line_delimited(&self) -> bool {
+        self.delimiter != DEFAULT_DELIMITER
+    }
+}
+
+// SQLFile holds queries read from an sql file
+//   the queries are grouped by the tag they are given in the file, tags keep the order they
+//   are declared in
+//   metadata holds the "-- key: value" comments found before the first tag whose lowercase key
+//   is one of METADATA_KEYS
+#[derive(Debug, Clone)]
 pub struct SQLFile {
     pub name: String,
-    pub query_hash_map: HashMap<Tag, QuerySet>,
+    pub metadata: IndexMap<String, String>,
+    pub query_hash_map: IndexMap<Tag, QuerySet>,
 }
 
 // QuerySet holds a groups of query strings as well as the computed hash of the entire set
-#[derive(Debug)]
+//   tag_line is the line its tag is declared on, query_lines holds the line each query starts on
+//   transaction is false when its tag is declared (no-transaction), for queries like
+//   CREATE INDEX CONCURRENTLY that postgres won't run inside a transaction
+//   normalize_whitespace is whether whitespace was normalized before hashing
+#[derive(Debug, Clone)]
 pub struct QuerySet {
     pub queries: Vec<String>,
+    pub tag_line: i32,
+    pub query_lines: Vec<i32>,
     pub hash: String,
+    pub transaction: bool,
+    pub normalize_whitespace: bool,
+}
+
+impl QuerySet {
+    // the hash of the queries with any algorithm
+    pub fn checksum(&self, algorithm: ChecksumAlgorithm) -> String {
+        checksum_queries(&self.queries, self.normalize_whitespace, algorithm)
+    }
+
+    // whether a recorded hash is of these queries, recorded hashes made with another algorithm
+    // than the file was parsed with are compared by rehashing the queries with that algorithm
+    pub fn matches_hash(&self, hash: &str) -> bool {
+        if self.hash == hash {
+            return true;
+        }
+        let algorithm = ChecksumAlgorithm::of_hash(hash);
+        algorithm != ChecksumAlgorithm::of_hash(&self.hash) && self.checksum(algorithm) == hash
+    }
+}
+
+fn checksum_queries(
+    queries: &[String],
+    normalize_whitespace: bool,
+    algorithm: ChecksumAlgorithm,
+) -> String {
+    let mut all_queries = String::new();
+
+    for query in queries.iter() {
+        if normalize_whitespace {
+            all_queries.push_str(&query.split_whitespace().collect::<Vec<_>>().join(" "));
+        } else {
+            all_queries.push_str(query);
+        }
+    }
+
+    algorithm.checksum(&all_queries)
+}
+
+// the file name and a line per tag with its query count and the start of its hash, e.g.
+//   001_users
+//     up    2 queries  5EA918FAC556
+//     down  1 queries  0C2B7D1E9A40
+impl fmt::Display for SQLFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        let tag_width = self.query_hash_map.keys().map(|tag| tag.len()).max();
+        for (tag, query_set) in self.iter() {
+            write!(
+                f,
+                "\n  {:<tag_width$}  {} queries  {}",
+                tag,
+                query_set.queries.len(),
+                query_set.hash.get(..12).unwrap_or(&query_set.hash),
+                tag_width = tag_width.unwrap_or(0)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// TagSummary describes a tag's query set without the query text
+#[derive(Debug, PartialEq)]
+pub struct TagSummary {
+    pub tag: Tag,
+    pub tag_line: i32,
+    pub query_count: usize,
+    pub query_lines: Vec<i32>,
 }
 
 impl SQLFile {
     // reads sql from a file, ultimately uses, new_from_string
-    pub fn new_from_file<P>(path: P) -> SQLFileResult<SQLFile>
+    pub fn new_from_file<P>(path: P, configuration: &ParserConfiguration) -> SQLFileResult<SQLFile>
     where
         P: AsRef<Path>,
     {
-        if let Some(filename) = Self::file_stem(&path) {
-            if let Ok(st) = fs::read_to_string(path) {
-                return Self::new_from_string(&filename, &st);
+        if let Some(filename) = Self::file_stem(&path, configuration) {
+            if let Ok(file) = File::open(path) {
+                return Self::new_from_reader(&filename, BufReader::new(file), configuration);
             }
 
             return Err(SQLFileError::CouldNoReadFile);
@@ -76,92 +315,279 @@ impl SQLFile {
         Err(SQLFileError::CouldNotGetFilename)
     }
 
-    // helper function to get the file's stem name: this_file.txt -> this_file
-    fn file_stem<P>(path: P) -> Option<String>
+    // reads and parses the files concurrently, the results are in the same order as the paths
+    // so one failing file doesn't hide the others
+    pub fn new_from_files(
+        paths: &[PathBuf],
+        configuration: &ParserConfiguration,
+    ) -> Vec<SQLFileResult<SQLFile>> {
+        paths
+            .par_iter()
+            .map(|path| Self::new_from_file(path, configuration))
+            .collect()
+    }
+
+    // describes every tag of the file in the order they were declared
+    pub fn summary(&self) -> Vec<TagSummary> {
+        self.iter()
+            .map(|(tag, query_set)| TagSummary {
+                tag: tag.clone(),
+                tag_line: query_set.tag_line,
+                query_count: query_set.queries.len(),
+                query_lines: query_set.query_lines.clone(),
+            })
+            .collect()
+    }
+
+    /// Every tag and its query set, in the order the tags are declared in the file.
+    ///
+    /// ```
+    /// use osprey::{ParserConfiguration, SQLFile};
+    ///
+    /// let text = "-- tag:up\nCREATE TABLE users (id SERIAL);\n-- tag:down\nDROP TABLE users;";
+    /// let file = SQLFile::new_from_string("001_users", text, &ParserConfiguration::new()).unwrap();
+    ///
+    /// let tags: Vec<_> = file
+    ///     .iter()
+    ///     .map(|(tag, query_set)| (tag.as_str(), query_set.queries.len()))
+    ///     .collect();
+    /// assert_eq!(tags, vec![("up", 1), ("down", 1)]);
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = (&Tag, &QuerySet)> {
+        self.query_hash_map.iter()
+    }
+
+    // looks up the query set of a tag, tags are compared case insensitively
+    pub fn get_query_set(&self, tag: &str) -> Option<&QuerySet> {
+        self.query_hash_map.get(&tag.to_lowercase())
+    }
+
+    // the version number the file name starts with, e.g. 1 for 001_init and 20240115 for
+    // V20240115__add_index
+    pub fn version(&self) -> Option<u64> {
+        let name = match self.name.strip_prefix(|c| c == 'V' || c == 'v') {
+            Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => rest,
+            _ => &self.name,
+        };
+        let end = name
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(name.len());
+        name[..end].parse().ok()
+    }
+
+    // the migration's name from its file name. only the final .sql and a template suffix after
+    // it are stripped, 001.up.sql -> 001.up and 001_init.sql.j2 -> 001_init. other files lose
+    // their last extension: this_file.txt -> this_file
+    fn file_stem<P>(path: P, configuration: &ParserConfiguration) -> Option<String>
     where
         P: AsRef<Path>,
     {
-        let os_filename = path.as_ref().file_stem()?;
-        let str_filename = os_filename.to_str()?;
-        Some(str_filename.to_string())
+        let file_name = path.as_ref().file_name()?.to_str()?;
+        let file_name = match &configuration.template_suffix {
+            Some(suffix) => file_name.strip_suffix(suffix.as_str()).unwrap_or(file_name),
+            None => file_name,
+        };
+
+        let stem = match file_name.len().checked_sub(".sql".len()) {
+            Some(end)
+                if file_name.is_char_boundary(end)
+                    && file_name[end..].eq_ignore_ascii_case(".sql") =>
+            {
+                &file_name[..end]
+            }
+            _ => Path::new(file_name).file_stem()?.to_str()?,
+        };
+        if stem.is_empty() {
+            return None;
+        }
+        Some(stem.to_string())
     }
 
     // parses a string into a group of queries
-    pub fn new_from_string(name: &str, text: &str) -> SQLFileResult<SQLFile> {
-        let mut tag_name = String::new();
-        let mut query_hash_map = HashMap::new();
-        let mut current_query_set = QueryReadState::new();
+    pub fn new_from_string(
+        name: &str,
+        text: &str,
+        configuration: &ParserConfiguration,
+    ) -> SQLFileResult<SQLFile> {
+        Self::new_from_reader(name, text.as_bytes(), configuration)
+    }
+
+    // parses a group of queries one line at a time, only the current line and the queries
+    // read so far are held in memory rather than the whole text
+    pub fn new_from_reader<R: BufRead>(
+        name: &str,
+        reader: R,
+        configuration: &ParserConfiguration,
+    ) -> SQLFileResult<SQLFile> {
+        let mut errors = SyntaxErrors::new(configuration.lenient);
+        let mut tag_declared = false;
+        let mut tag_names: Vec<Tag> = vec![];
+        let mut metadata = IndexMap::new();
+        let mut query_hash_map = IndexMap::new();
+        let mut current_query_set = QueryReadState::new(configuration);
         let mut line_count = 0;
-        let lines = text.split('\n');
 
-        for line in lines {
+        for line in ReaderLines::new(reader) {
+            let line = line?;
+            line_count += 1;
+            // some editors start files with a byte order mark, it isn't part of the first line
+            let line = match line_count {
+                1 => line.strip_prefix('\u{FEFF}').unwrap_or(&line),
+                _ => &line,
+            };
+            // files with windows line endings hash the same as their unix counterparts
+            let line = line.strip_suffix('\r').unwrap_or(line);
             let file_line = FileLine::new(line);
 
-            if file_line.is_finishing_query() {
-                if tag_name.is_empty() {
-                    return SyntaxErrorMessage::QueryGivenNoTag.to_err(line_count);
-                }
+            // rows of COPY ... FROM STDIN data are part of the COPY query up to the \. line that
+            // ends them, even rows that look like comments or tags
+            if current_query_set.in_copy_data() {
+                current_query_set.read_copy_line(&file_line.original_line);
+                continue;
+            }
 
-                current_query_set.finish_query(&file_line.original_line);
+            // everything inside a quoted string is query text and everything inside a block
+            // comment is ignored, even lines that look like comments or tags
+            if current_query_set.in_quote() || current_query_set.in_block_comment() {
+                current_query_set.read_query_line(&file_line.original_line, line_count);
                 continue;
             }
 
-            if file_line.is_tag_name() {
+            if file_line.is_tag_name(&configuration.tag_prefix) {
                 if current_query_set.has_unfinished_query() {
-                    return SyntaxErrorMessage::TagNameIncompleteQuery.to_err(line_count);
-                }
-
-                if !tag_name.is_empty() && current_query_set.is_empty() {
-                    return SyntaxErrorMessage::NoQueryForTag.to_err(line_count);
+                    errors
+                        .report(SyntaxErrorMessage::TagNameIncompleteQuery.to_error(line_count))?;
+                    current_query_set.discard_unfinished_query();
                 }
 
-                if !tag_name.is_empty() && !current_query_set.is_empty() {
+                if tag_declared {
+                    if let Err(err) = current_query_set.check_complete(line_count) {
+                        errors.report(err)?;
+                    }
                     let query_set = current_query_set.compute_hash().into_query_set();
-                    query_hash_map.insert(tag_name, query_set);
-                    current_query_set = QueryReadState::new();
+                    Self::insert_query_set(&mut query_hash_map, tag_names, query_set);
+                    current_query_set = QueryReadState::new(configuration);
                 }
 
-                match file_line.get_tag_name() {
-                    Some(t) => tag_name = t,
-                    None => {
-                        return SyntaxErrorMessage::CouldNotParseTagName.to_err(line_count);
+                // a tag line that can't be parsed still starts a new query set, just one
+                // without a name, so its queries aren't reported as having no tag
+                tag_declared = true;
+                tag_names = vec![];
+                match file_line.get_tag(&configuration.tag_prefix) {
+                    Ok(declaration) => {
+                        for name in declaration.names {
+                            if query_hash_map.contains_key(&name) || tag_names.contains(&name) {
+                                errors.report(
+                                    SyntaxErrorMessage::DuplicateTag(name).to_error(line_count),
+                                )?;
+                            } else {
+                                tag_names.push(name);
+                            }
+                        }
+                        current_query_set.noop = declaration.noop;
+                        current_query_set.transaction = declaration.transaction;
+                        current_query_set.tag_line = line_count;
                     }
+                    Err(message) => errors.report(message.to_error(line_count))?,
                 }
                 continue;
             }
 
+            if configuration.is_line_delimited() && file_line.is_delimiter(&configuration.delimiter)
+            {
+                current_query_set.end_query();
+                continue;
+            }
+
+            // comments may also sit in the middle of a query, they're left out of it
             if file_line.is_comment_line() {
-                if current_query_set.has_unfinished_query() {
-                    return SyntaxErrorMessage::CommentInQuery.to_err(line_count);
+                if !tag_declared {
+                    if let Some((key, value)) = file_line.get_metadata() {
+                        metadata.insert(key, value);
+                    }
                 }
                 continue;
             }
 
             if file_line.is_query_string() {
-                current_query_set.add_query_string(&file_line.original_line);
-            }
-            line_count += 1;
-        }
-
-        if current_query_set.has_unfinished_query() {
-            return SyntaxErrorMessage::EOFIncompleteQuery.to_err(line_count);
-        }
+                current_query_set.read_query_line(&file_line.original_line, line_count);
 
-        if tag_name.is_empty() && current_query_set.is_empty() {
-            return SyntaxErrorMessage::NoQueriesFound.to_err(line_count);
+                if !tag_declared && current_query_set.has_query_text() {
+                    errors.report(SyntaxErrorMessage::QueryGivenNoTag.to_error(line_count))?;
+                    // carry on as if the queries had a tag so they're only reported once
+                    tag_declared = true;
+                }
+            }
         }
 
-        if !tag_name.is_empty() && current_query_set.is_empty() {
-            return SyntaxErrorMessage::NoQueryForTag.to_err(line_count);
+        if current_query_set.in_block_comment() {
+            errors.report(SyntaxErrorMessage::EOFUnclosedComment.to_error(line_count))?;
+        } else if current_query_set.has_unfinished_query() {
+            errors.report(SyntaxErrorMessage::EOFIncompleteQuery.to_error(line_count))?;
+        } else if !tag_declared && current_query_set.is_empty() {
+            errors.report(SyntaxErrorMessage::NoQueriesFound.to_error(line_count))?;
+        } else if let Err(err) = current_query_set.check_complete(line_count) {
+            errors.report(err)?;
         }
+        errors.finish()?;
 
         let query_set = current_query_set.compute_hash().into_query_set();
-        query_hash_map.insert(tag_name, query_set);
+        Self::insert_query_set(&mut query_hash_map, tag_names, query_set);
         Ok(SQLFile {
             name: name.to_string(),
+            metadata,
             query_hash_map,
         })
     }
+
+    // a query set is stored under every name its tag was declared with
+    fn insert_query_set(
+        query_hash_map: &mut IndexMap<Tag, QuerySet>,
+        tag_names: Vec<Tag>,
+        query_set: QuerySet,
+    ) {
+        for tag_name in tag_names {
+            query_hash_map.insert(tag_name, query_set.clone());
+        }
+    }
+}
+
+// ReaderLines splits a reader's text on \n the same way str::split does, so text ending in a
+//   newline has an empty last line
+struct ReaderLines<R> {
+    reader: R,
+    done: bool,
+}
+
+impl<R: BufRead> ReaderLines<R> {
+    fn new(reader: R) -> ReaderLines<R> {
+        ReaderLines {
+            reader,
+            done: false,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for ReaderLines<R> {
+    type Item = SQLFileResult<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut line = vec![];
+        if self.reader.read_until(b'\n', &mut line).is_err() {
+            self.done = true;
+            return Some(Err(SQLFileError::CouldNoReadFile));
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        } else {
+            self.done = true;
+        }
+        Some(String::from_utf8(line).map_err(|_| SQLFileError::CouldNoReadFile))
+    }
 }
 
 // FileLine gives basic descriptions of a line of a file
@@ -185,63 +611,308 @@ impl FileLine {
         self.line.is_empty()
     }
 
-    fn is_tag_name(&self) -> bool {
-        self.is_comment_line() && self.line.contains(TAG_LINE)
+    fn is_tag_name(&self, tag_prefix: &str) -> bool {
+        self.is_comment_line() && self.line.contains(tag_prefix)
+    }
+
+    fn get_tag_name(&self, tag_prefix: &str) -> Option<String> {
+        // find gives a byte offset, slicing there is safe since it's the start of the prefix
+        let begin = self.line.find(tag_prefix)? + tag_prefix.len();
+
+        // tags are case insensitive, Up, UP and up are all the same tag
+        let tag = self.line[begin..].trim().to_lowercase();
+        if tag.is_empty() {
+            return None;
+        }
+        Some(tag)
     }
 
-    fn get_tag_name(&self) -> Option<String> {
-        let indicies: Vec<_> = self.line.match_indices(TAG_LINE).collect();
-        let tag_line_len = TAG_LINE.len();
-        if !indicies.is_empty() {
-            let first_index = indicies[0].0;
-            let begin = first_index + tag_line_len;
-            let mut tag = String::new();
-            for i in begin..self.line.len() {
-                tag.push(self.line.chars().nth(i).unwrap());
+    // parses a tag line into the tag's names and the options in parentheses after them,
+    //   -- tag:down (noop)
+    //   -- tag:up (no-transaction)
+    //   -- tag:up,init
+    fn get_tag(&self, tag_prefix: &str) -> Result<TagDeclaration, SyntaxErrorMessage> {
+        let text = self
+            .get_tag_name(tag_prefix)
+            .ok_or(SyntaxErrorMessage::CouldNotParseTagName)?;
+
+        let (name, options) = match text.find('(') {
+            Some(open) => {
+                let options = text[open + 1..]
+                    .strip_suffix(')')
+                    .ok_or(SyntaxErrorMessage::CouldNotParseTagName)?;
+                (
+                    text[..open].trim(),
+                    options.split(',').map(str::trim).collect(),
+                )
             }
+            None => (text.as_str(), vec![]),
+        };
+
+        let names: Vec<Tag> = name.split(',').map(|n| n.trim().to_string()).collect();
+        if names.iter().any(|n| n.is_empty()) {
+            return Err(SyntaxErrorMessage::CouldNotParseTagName);
+        }
+
+        // tags end up in the migrations table, keep them to characters that are safe there
+        if let Some(invalid) = names.iter().find(|n| !is_valid_name(n)) {
+            return Err(SyntaxErrorMessage::InvalidTagName(invalid.clone()));
+        }
 
-            tag = tag.trim().to_string();
-            if tag.is_empty() {
-                return None;
+        let mut declaration = TagDeclaration {
+            names,
+            noop: false,
+            transaction: true,
+        };
+        for option in options {
+            match option {
+                "noop" => declaration.noop = true,
+                "no-transaction" => declaration.transaction = false,
+                _ => return Err(SyntaxErrorMessage::UnknownTagOption(option.to_string())),
             }
-            return Some(tag);
         }
+        Ok(declaration)
+    }
 
-        None
+    // reads a "-- key: value" comment line with one of the metadata keys
+    fn get_metadata(&self) -> Option<(String, String)> {
+        let (key, value) = self.line.trim_start_matches('-').split_once(':')?;
+        let key = key.trim().to_lowercase();
+        if !METADATA_KEYS.contains(&key.as_str()) {
+            return None;
+        }
+        Some((key, value.trim().to_string()))
     }
 
     fn is_comment_line(&self) -> bool {
         self.line.starts_with("--")
     }
 
+    fn is_delimiter(&self, delimiter: &str) -> bool {
+        self.line.eq_ignore_ascii_case(delimiter)
+    }
+
     fn is_query_string(&self) -> bool {
         !self.is_empty() && !self.is_comment_line()
     }
+}
+
+// SyntaxErrors decides what happens to the syntax errors found while parsing. Strict parsing
+//   fails on the first one, lenient parsing collects them all to report them together
+struct SyntaxErrors {
+    lenient: bool,
+    errors: Vec<SQLFileError>,
+}
+
+impl SyntaxErrors {
+    fn new(lenient: bool) -> SyntaxErrors {
+        SyntaxErrors {
+            lenient,
+            errors: vec![],
+        }
+    }
+
+    fn report(&mut self, error: SQLFileError) -> SQLFileResult<()> {
+        if !self.lenient {
+            return Err(error);
+        }
+        self.errors.push(error);
+        Ok(())
+    }
 
-    fn is_finishing_query(&self) -> bool {
-        self.is_query_string() && self.line.ends_with(';')
+    fn finish(self) -> SQLFileResult<()> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        Err(SQLFileError::SyntaxErrors(self.errors))
     }
 }
 
+// TagDeclaration is what a tag line declares
+//   names holds the tag's name followed by any aliases for the same query set
+//   noop marks a tag that intentionally has no queries
+//   transaction is false when the tag's queries have to run outside of a transaction
+#[derive(Debug)]
+struct TagDeclaration {
+    names: Vec<Tag>,
+    noop: bool,
+    transaction: bool,
+}
+
 // QueryReadState gives basic information of where in an sql query string the file parser is.
 //   As this state determines if a query is finished it'll be pushed into the queries vector.
 //   Current queries being read by the parser are held in current_query
+//   quote holds the kind of quoted string the parser is inside of, semicolons in it don't end a query
+//   comment_depth counts the /* block comments */ the parser is inside of, they may be nested
+//   semicolon_delimited is false when queries are ended by a delimiter line instead
+//   normalize_whitespace is true when whitespace shouldn't affect the hash
+//   checksum_algorithm is the algorithm the hash is computed with
+//   query_lines holds the line every query, including current_query, starts on
+//   tag_line is the line the tag of this query set is declared on
+//   noop is true when the tag of this query set is declared to have no queries
+//   transaction is false when the tag of this query set is declared (no-transaction)
+//   copy_data is true while reading the data rows of a COPY ... FROM STDIN query
 #[derive(Debug)]
 struct QueryReadState {
     queries: Vec<String>,
+    tag_line: i32,
+    query_lines: Vec<i32>,
+    line_number: i32,
     current_query: String,
+    quote: Option<Quote>,
+    comment_depth: usize,
+    semicolon_delimited: bool,
+    normalize_whitespace: bool,
+    checksum_algorithm: ChecksumAlgorithm,
+    noop: bool,
+    transaction: bool,
+    copy_data: bool,
     hash: String,
 }
 
+// function, trigger and DO block bodies are quoted strings, so tracking quotes is what keeps
+//   their BEGIN/END blocks and the semicolons in them part of one query. BEGIN and END aren't
+//   tracked on their own since BEGIN; ... COMMIT; transactions use them without nesting
+#[derive(Debug)]
+enum Quote {
+    // 'a string literal', a '' inside of it is an escaped quote
+    Single,
+    // $$ or $tag$ quoted string, holds the delimiter that closes it
+    Dollar(String),
+}
+
 impl QueryReadState {
-    fn new() -> QueryReadState {
+    fn new(configuration: &ParserConfiguration) -> QueryReadState {
         QueryReadState {
             queries: vec![],
+            tag_line: 0,
+            query_lines: vec![],
+            line_number: 0,
             current_query: String::new(),
+            quote: None,
+            comment_depth: 0,
+            semicolon_delimited: !configuration.is_line_delimited(),
+            normalize_whitespace: configuration.normalize_whitespace,
+            checksum_algorithm: configuration.checksum_algorithm,
+            noop: false,
+            transaction: true,
+            copy_data: false,
             hash: "".to_string(),
         }
     }
 
+    fn in_quote(&self) -> bool {
+        self.quote.is_some()
+    }
+
+    fn in_block_comment(&self) -> bool {
+        self.comment_depth > 0
+    }
+
+    // reads a line of query text, finishing the current query at every semicolon that
+    // isn't inside a quoted string. Whitespace trailing the last semicolon stays with the
+    // finished query so the hash matches the line as written. Block comments and trailing
+    // -- comments are left out of the query entirely.
+    fn read_query_line(&mut self, line: &str, line_number: i32) {
+        self.line_number = line_number;
+        let bytes = line.as_bytes();
+        let mut segment = String::new();
+        let mut stripped_comment = self.in_block_comment();
+        let mut start = 0;
+        let mut i = 0;
+
+        while i < bytes.len() {
+            if self.in_block_comment() {
+                if bytes[i..].starts_with(b"*/") {
+                    self.comment_depth -= 1;
+                    i += 2;
+                    start = i;
+                } else if bytes[i..].starts_with(b"/*") {
+                    self.comment_depth += 1;
+                    i += 2;
+                } else {
+                    i += 1;
+                }
+                continue;
+            }
+
+            match &self.quote {
+                Some(Quote::Single) => {
+                    if bytes[i] == b'\'' {
+                        if bytes.get(i + 1) == Some(&b'\'') {
+                            i += 2;
+                            continue;
+                        }
+                        self.quote = None;
+                    }
+                    i += 1;
+                    continue;
+                }
+                Some(Quote::Dollar(tag)) => {
+                    if bytes[i..].starts_with(tag.as_bytes()) {
+                        i += tag.len();
+                        self.quote = None;
+                    } else {
+                        i += 1;
+                    }
+                    continue;
+                }
+                None => {}
+            }
+
+            match bytes[i] {
+                b'\'' => self.quote = Some(Quote::Single),
+                b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                    segment.push_str(line[start..i].trim_end());
+                    stripped_comment = true;
+                    start = bytes.len();
+                    break;
+                }
+                b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                    segment.push_str(&line[start..i]);
+                    self.comment_depth = 1;
+                    stripped_comment = true;
+                    i += 2;
+                    continue;
+                }
+                b'$' if i == 0 || !is_identifier_byte(bytes[i - 1]) => {
+                    if let Some(tag) = dollar_quote_tag(&line[i..]) {
+                        i += tag.len();
+                        self.quote = Some(Quote::Dollar(tag));
+                        continue;
+                    }
+                }
+                b';' if self.semicolon_delimited => {
+                    segment.push_str(&line[start..=i]);
+                    let rest = &line[i + 1..];
+                    if rest.trim().is_empty() {
+                        segment.push_str(rest);
+                        self.finish_query(&segment);
+                        return;
+                    }
+                    self.finish_query(&segment);
+                    if self.in_copy_data() {
+                        return;
+                    }
+                    segment.clear();
+                    start = i + 1;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+
+        if !self.in_block_comment() {
+            segment.push_str(&line[start..]);
+        }
+
+        // a line left blank by removing a comment isn't part of the query
+        if segment.trim().is_empty() && (stripped_comment || self.current_query.is_empty()) {
+            return;
+        }
+        self.add_query_string(&segment);
+    }
+
     fn is_empty(&self) -> bool {
         self.queries.len() == 0
     }
@@ -250,41 +921,142 @@ impl QueryReadState {
         !self.current_query.is_empty()
     }
 
+    // a query set is complete when it has queries, or has none because its tag is a noop.
+    // comments don't count as queries, a tag followed only by comments has to be a noop too
+    fn check_complete(&self, line_number: i32) -> SQLFileResult<()> {
+        if self.noop && !self.is_empty() {
+            return Err(SyntaxErrorMessage::QueryForNoopTag.to_error(self.query_lines[0]));
+        }
+        if !self.noop && self.is_empty() {
+            return Err(SyntaxErrorMessage::NoQueryForTag.to_error(line_number));
+        }
+        Ok(())
+    }
+
+    fn discard_unfinished_query(&mut self) {
+        if self.has_unfinished_query() {
+            self.query_lines.pop();
+            self.current_query = String::new();
+        }
+    }
+
+    fn has_query_text(&self) -> bool {
+        !self.is_empty() || self.has_unfinished_query()
+    }
+
     fn add_query_string(&mut self, st: &str) {
-        if !self.current_query.is_empty() {
+        if self.current_query.is_empty() {
+            self.query_lines.push(self.line_number);
+        } else {
             self.current_query.push('\n');
         }
         self.current_query.push_str(st);
     }
 
-    fn finish_query(&mut self, st: &str) {
-        self.add_query_string(st);
-        self.queries.push(self.current_query.clone());
-        self.current_query = String::new();
+    fn in_copy_data(&self) -> bool {
+        self.copy_data
     }
 
-    fn compute_hash(mut self) -> Self {
-        let mut all_queries = String::new();
-
-        for query in self.queries.iter() {
-            all_queries.push_str(query);
+    // reads a row of COPY data, the query ends with the \. row
+    fn read_copy_line(&mut self, line: &str) {
+        self.add_query_string(line);
+        if line.trim_end() == "\\." {
+            self.copy_data = false;
+            self.end_query();
         }
+    }
 
-        let mut hasher = Sha256::new();
-        hasher.update(all_queries);
+    // a COPY ... FROM STDIN query isn't finished by its semicolon, the data rows that follow it
+    // belong to it as well
+    fn finish_query(&mut self, st: &str) {
+        self.add_query_string(st);
+        if is_copy_from_stdin(&self.current_query) {
+            self.copy_data = true;
+            return;
+        }
+        self.end_query();
+    }
 
-        let hash = format!("{:X}", hasher.finalize());
+    fn end_query(&mut self) {
+        if self.has_unfinished_query() {
+            self.queries.push(self.current_query.clone());
+            self.current_query = String::new();
+        }
+    }
 
-        self.hash = hash;
+    fn compute_hash(mut self) -> Self {
+        self.hash = checksum_queries(
+            &self.queries,
+            self.normalize_whitespace,
+            self.checksum_algorithm,
+        );
         self
     }
 
     fn into_query_set(self) -> QuerySet {
         QuerySet {
             queries: self.queries,
+            tag_line: self.tag_line,
+            query_lines: self.query_lines,
             hash: self.hash,
+            transaction: self.transaction,
+            normalize_whitespace: self.normalize_whitespace,
+        }
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    name.chars()
+        .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+// splits a COPY ... FROM STDIN query into its statement and data rows, which are sent to the
+// database separately. the statement ends with the first line ending with a semicolon, the
+// \. row ending the data is left out
+pub fn split_copy_data(query: &str) -> Option<(&str, &str)> {
+    let mut statement_end = 0;
+    for line in query.split_inclusive('\n') {
+        statement_end += line.len();
+        if line.trim_end().ends_with(';') {
+            break;
+        }
+    }
+    let (statement, data) = query.split_at(statement_end);
+    if !is_copy_from_stdin(statement) {
+        return None;
+    }
+    let data = data.trim_end().strip_suffix("\\.").unwrap_or(data);
+    Some((statement.trim_end(), data))
+}
+
+fn is_copy_from_stdin(query: &str) -> bool {
+    let words: Vec<String> = query
+        .split_whitespace()
+        .map(|word| word.trim_end_matches(';').to_uppercase())
+        .collect();
+
+    words.first().map(String::as_str) == Some("COPY")
+        && words
+            .windows(2)
+            .any(|pair| pair[0] == "FROM" && pair[1] == "STDIN")
+}
+
+fn is_identifier_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'$'
+}
+
+// returns the delimiter of a dollar quoted string ($$ or $tag$) if text begins with one.
+//   $1 style positional parameters are not delimiters since a tag can't start with a digit
+fn dollar_quote_tag(text: &str) -> Option<String> {
+    for (i, c) in text.char_indices().skip(1) {
+        if c == '$' {
+            return Some(text[..=i].to_string());
+        }
+        if !(c.is_ascii_alphanumeric() || c == '_') || (i == 1 && c.is_ascii_digit()) {
+            return None;
         }
     }
+    None
 }
 
 #[cfg(test)]
@@ -303,27 +1075,28 @@ mod tests {
     fn test_valid_sql_file() {
         let valid_file = "\n-- tag:up \nSELECT * FROM atable WHERE *;";
 
-        let sql_file = SQLFile::new_from_string("filename", valid_file);
+        let sql_file =
+            SQLFile::new_from_string("filename", valid_file, &ParserConfiguration::new());
         assert!(sql_file.is_ok());
 
         let file = sql_file.unwrap();
         assert_eq!(file.name.as_str(), "filename");
         assert!(file.query_hash_map.len() == 1);
-        assert!(file.query_hash_map.contains_key(&"up".to_string()));
+        assert!(file.query_hash_map.contains_key("up"));
     }
 
     #[test]
     fn test_no_query_with_tag() {
         let no_query = "\n-- tag:up";
 
-        let sql_file = SQLFile::new_from_string("filename", no_query);
+        let sql_file = SQLFile::new_from_string("filename", no_query, &ParserConfiguration::new());
         assert!(check_sem(sql_file, SyntaxErrorMessage::NoQueryForTag));
     }
 
     #[test]
     fn test_query_with_no_tag() {
         let no_tag = "SELECT * FROM atable WHERE *;";
-        let maybe_sql_file = SQLFile::new_from_string("f", no_tag);
+        let maybe_sql_file = SQLFile::new_from_string("f", no_tag, &ParserConfiguration::new());
         assert!(check_sem(
             maybe_sql_file,
             SyntaxErrorMessage::QueryGivenNoTag
@@ -333,7 +1106,8 @@ mod tests {
     #[test]
     fn test_no_tag_name() {
         let no_tag_name = "\n-- tag:\nSELECT * FROM atable WHERE *;";
-        let maybe_sql_file = SQLFile::new_from_string("f", no_tag_name);
+        let maybe_sql_file =
+            SQLFile::new_from_string("f", no_tag_name, &ParserConfiguration::new());
         assert!(check_sem(
             maybe_sql_file,
             SyntaxErrorMessage::CouldNotParseTagName
@@ -343,7 +1117,8 @@ mod tests {
     #[test]
     fn test_unfinished_query() {
         let unfinished_query = "\n-- tag: up\nSELECT * FROM atable WHERE *";
-        let maybe_sql_file = SQLFile::new_from_string("f", unfinished_query);
+        let maybe_sql_file =
+            SQLFile::new_from_string("f", unfinished_query, &ParserConfiguration::new());
         assert!(check_sem(
             maybe_sql_file,
             SyntaxErrorMessage::EOFIncompleteQuery
@@ -353,7 +1128,8 @@ mod tests {
     #[test]
     fn test_query_set_no_query() {
         let queries = "\n-- tag:up\nSELECT * FROM atable WHERE *;\n-- tag:down\nSELECT * FROM atable WHERE *;\n-- tag:left\n-- tag:right\nSELECT * FROM atable WHERE *;";
-        let maybe_sql_file = SQLFile::new_from_string("filename", queries);
+        let maybe_sql_file =
+            SQLFile::new_from_string("filename", queries, &ParserConfiguration::new());
         assert!(check_sem(maybe_sql_file, SyntaxErrorMessage::NoQueryForTag));
     }
 
@@ -361,7 +1137,7 @@ mod tests {
     fn test_multiline_query() {
         let multiline: &str = "-- tag: up \nSELECT * FROM \natable WHERE \nacolumn=avalue;\n";
 
-        let maybe_sql_file = SQLFile::new_from_string("f", multiline);
+        let maybe_sql_file = SQLFile::new_from_string("f", multiline, &ParserConfiguration::new());
         assert!(maybe_sql_file.is_ok());
 
         let sql_file = maybe_sql_file.unwrap();
@@ -380,7 +1156,7 @@ mod tests {
     fn test_multiquery_set() {
         let queries = "\n-- tag:up\nSELECT * FROM onetable WHERE *;\nSELECT * FROM twotable WHERE *;\nSELECT * FROM threetable WHERE *;";
 
-        let maybe_sql_file = SQLFile::new_from_string("f", queries);
+        let maybe_sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new());
         assert!(maybe_sql_file.is_ok());
 
         let sql_file = maybe_sql_file.unwrap();
@@ -394,4 +1170,921 @@ mod tests {
         assert_eq!(&set.queries[1], "SELECT * FROM twotable WHERE *;");
         assert_eq!(&set.queries[2], "SELECT * FROM threetable WHERE *;")
     }
+
+    #[test]
+    fn test_dollar_quoted_function() {
+        let function = "-- tag:up
+CREATE OR REPLACE FUNCTION audit_trigger() RETURNS trigger AS $body$
+BEGIN
+    IF NEW.amount > 100 THEN
+        BEGIN
+            INSERT INTO audit (amount) VALUES (NEW.amount);
+        END;
+    END IF;
+    RETURN NEW;
+END;
+$body$ LANGUAGE plpgsql;";
+
+        let sql_file =
+            SQLFile::new_from_string("f", function, &ParserConfiguration::new()).unwrap();
+        let set = sql_file.query_hash_map.get("up").unwrap();
+        assert_eq!(set.queries.len(), 1);
+        assert!(set.queries[0].starts_with("CREATE OR REPLACE FUNCTION"));
+        assert!(set.queries[0].ends_with("$body$ LANGUAGE plpgsql;"));
+        assert!(set.queries[0].contains("        END;\n    END IF;"));
+    }
+
+    #[test]
+    fn test_unterminated_dollar_quote() {
+        let function = "-- tag:up\nDO $$ BEGIN PERFORM 1; END;\n";
+        let maybe_sql_file = SQLFile::new_from_string("f", function, &ParserConfiguration::new());
+        assert!(check_sem(
+            maybe_sql_file,
+            SyntaxErrorMessage::EOFIncompleteQuery
+        ));
+    }
+
+    #[test]
+    fn test_semicolon_in_string_literal() {
+        let queries = "-- tag:up\nINSERT INTO t VALUES ('hello; world');\nINSERT INTO t VALUES ('it''s; here');";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        let set = sql_file.query_hash_map.get("up").unwrap();
+        assert_eq!(set.queries.len(), 2);
+        assert_eq!(&set.queries[0], "INSERT INTO t VALUES ('hello; world');");
+        assert_eq!(&set.queries[1], "INSERT INTO t VALUES ('it''s; here');");
+    }
+
+    #[test]
+    fn test_multiline_string_literal() {
+        let queries = "-- tag:up\nINSERT INTO t VALUES ('first;\n-- tag:not_a_tag\nlast');";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        assert_eq!(sql_file.query_hash_map.len(), 1);
+        let set = sql_file.query_hash_map.get("up").unwrap();
+        assert_eq!(set.queries.len(), 1);
+        assert_eq!(
+            &set.queries[0],
+            "INSERT INTO t VALUES ('first;\n-- tag:not_a_tag\nlast');"
+        );
+    }
+
+    #[test]
+    fn test_statements_sharing_a_line() {
+        let shared = "-- tag:up\nINSERT INTO t VALUES ('a;'); INSERT INTO t VALUES ('b');";
+        let separate = "-- tag:up\nINSERT INTO t VALUES ('a;');\n INSERT INTO t VALUES ('b');";
+
+        let shared_file =
+            SQLFile::new_from_string("f", shared, &ParserConfiguration::new()).unwrap();
+        let shared_set = shared_file.query_hash_map.get("up").unwrap();
+        assert_eq!(shared_set.queries.len(), 2);
+        assert_eq!(&shared_set.queries[0], "INSERT INTO t VALUES ('a;');");
+        assert_eq!(&shared_set.queries[1], " INSERT INTO t VALUES ('b');");
+
+        let separate_file =
+            SQLFile::new_from_string("f", separate, &ParserConfiguration::new()).unwrap();
+        let separate_set = separate_file.query_hash_map.get("up").unwrap();
+        assert_eq!(shared_set.hash, separate_set.hash);
+    }
+
+    #[test]
+    fn test_block_comments() {
+        let plain = "-- tag:up\nCREATE TABLE a (id INT);\nSELECT *\nFROM a;";
+        let commented = "/* file header\n   -- tag:not_a_tag\n*/\n-- tag:up\nCREATE TABLE a (id INT); /* trailing */\n/* one line */\nSELECT *\n/* spans /* nested */\n lines */\nFROM a;";
+
+        let plain_file = SQLFile::new_from_string("f", plain, &ParserConfiguration::new()).unwrap();
+        let commented_file =
+            SQLFile::new_from_string("f", commented, &ParserConfiguration::new()).unwrap();
+        assert_eq!(commented_file.query_hash_map.len(), 1);
+
+        let plain_set = plain_file.query_hash_map.get("up").unwrap();
+        let commented_set = commented_file.query_hash_map.get("up").unwrap();
+        assert_eq!(plain_set.queries, commented_set.queries);
+        assert_eq!(plain_set.hash, commented_set.hash);
+    }
+
+    #[test]
+    fn test_block_comment_in_string_literal() {
+        let queries = "-- tag:up\nINSERT INTO t VALUES ('/* not a comment */');";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        let set = sql_file.query_hash_map.get("up").unwrap();
+        assert_eq!(
+            &set.queries[0],
+            "INSERT INTO t VALUES ('/* not a comment */');"
+        );
+    }
+
+    #[test]
+    fn test_unclosed_block_comment() {
+        let unclosed = "-- tag:up\nSELECT * FROM atable; /* never\nclosed;";
+        let maybe_sql_file = SQLFile::new_from_string("f", unclosed, &ParserConfiguration::new());
+        assert!(check_sem(
+            maybe_sql_file,
+            SyntaxErrorMessage::EOFUnclosedComment
+        ));
+    }
+
+    #[test]
+    fn test_comments_inside_query() {
+        let commented = "-- tag:up\nCREATE TABLE users (\n    id SERIAL PRIMARY KEY, -- surrogate key\n    -- the address used to log in\n    email TEXT NOT NULL, -- unique per user\n    name TEXT -- display name\n); -- done";
+        let plain = "-- tag:up\nCREATE TABLE users (\n    id SERIAL PRIMARY KEY,\n    email TEXT NOT NULL,\n    name TEXT\n);";
+
+        let commented_file =
+            SQLFile::new_from_string("f", commented, &ParserConfiguration::new()).unwrap();
+        let commented_set = commented_file.query_hash_map.get("up").unwrap();
+        assert_eq!(commented_set.queries.len(), 1);
+
+        let plain_file = SQLFile::new_from_string("f", plain, &ParserConfiguration::new()).unwrap();
+        let plain_set = plain_file.query_hash_map.get("up").unwrap();
+        assert_eq!(commented_set.queries, plain_set.queries);
+        assert_eq!(commented_set.hash, plain_set.hash);
+    }
+
+    // recorded hashes have to stay valid, this one changing means every database that migrated
+    // such a file needs a sanity --fix
+    #[test]
+    fn test_inline_comment_hash() {
+        let commented = "-- tag:up\nCREATE TABLE users (\n    id SERIAL PRIMARY KEY, -- surrogate key\n    email TEXT NOT NULL, /* unique */\n    name TEXT\n);";
+
+        let sql_file =
+            SQLFile::new_from_string("f", commented, &ParserConfiguration::new()).unwrap();
+        assert_eq!(
+            sql_file.query_hash_map["up"].hash,
+            "B54747065F1EA3A80D492B7947F67B38FAC052BA7931C8A88A31F0602DA6C935"
+        );
+    }
+
+    #[test]
+    fn test_dashes_in_string_literal() {
+        let queries = "-- tag:up\nINSERT INTO t VALUES ('--not a comment');";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        let set = sql_file.query_hash_map.get("up").unwrap();
+        assert_eq!(&set.queries[0], "INSERT INTO t VALUES ('--not a comment');");
+    }
+
+    fn error_line(result: Result<SQLFile, SQLFileError>) -> i32 {
+        match result {
+            Err(SQLFileError::SyntaxError(line, _)) => line,
+            _ => panic!("expected a syntax error"),
+        }
+    }
+
+    #[test]
+    fn test_error_line_numbers() {
+        let no_tag = "-- a comment\n\nSELECT * FROM atable;";
+        assert_eq!(
+            error_line(SQLFile::new_from_string(
+                "f",
+                no_tag,
+                &ParserConfiguration::new()
+            )),
+            3
+        );
+
+        let empty_tag =
+            "-- tag:up\nSELECT * FROM atable;\n\n-- tag:down\n-- comment\n-- tag:left\nSELECT 1;";
+        assert_eq!(
+            error_line(SQLFile::new_from_string(
+                "f",
+                empty_tag,
+                &ParserConfiguration::new()
+            )),
+            6
+        );
+
+        let bad_tag = "-- tag:up\nSELECT *\nFROM atable;\n-- tag:\nSELECT 1;";
+        assert_eq!(
+            error_line(SQLFile::new_from_string(
+                "f",
+                bad_tag,
+                &ParserConfiguration::new()
+            )),
+            4
+        );
+
+        let unfinished = "-- tag:up\nSELECT *\n-- tag:down\nSELECT 1;";
+        assert_eq!(
+            error_line(SQLFile::new_from_string(
+                "f",
+                unfinished,
+                &ParserConfiguration::new()
+            )),
+            3
+        );
+
+        let eof = "-- tag:up\nSELECT 1;\nSELECT *\nFROM atable";
+        assert_eq!(
+            error_line(SQLFile::new_from_string(
+                "f",
+                eof,
+                &ParserConfiguration::new()
+            )),
+            4
+        );
+    }
+
+    #[test]
+    fn test_crlf_line_endings() {
+        let unix = "-- tag:up\nSELECT *\nFROM atable;\n-- tag:down\nSELECT 1;\n";
+        let windows = unix.replace('\n', "\r\n");
+
+        let unix_file = SQLFile::new_from_string("f", unix, &ParserConfiguration::new()).unwrap();
+        let windows_file =
+            SQLFile::new_from_string("f", &windows, &ParserConfiguration::new()).unwrap();
+        for tag in ["up", "down"].iter() {
+            let unix_set = unix_file.query_hash_map.get(*tag).unwrap();
+            let windows_set = windows_file.query_hash_map.get(*tag).unwrap();
+            assert_eq!(unix_set.queries, windows_set.queries);
+            assert_eq!(unix_set.hash, windows_set.hash);
+        }
+    }
+
+    #[test]
+    fn test_byte_order_mark() {
+        let plain = "-- tag:up\nSELECT * FROM atable;";
+        let with_bom = "\u{FEFF}-- tag:up\nSELECT * FROM atable;";
+
+        let plain_file = SQLFile::new_from_string("f", plain, &ParserConfiguration::new()).unwrap();
+        let bom_file =
+            SQLFile::new_from_string("f", with_bom, &ParserConfiguration::new()).unwrap();
+        let bom_set = bom_file.query_hash_map.get("up").unwrap();
+        assert_eq!(bom_set.queries.len(), 1);
+        assert_eq!(
+            bom_set.hash,
+            plain_file.query_hash_map.get("up").unwrap().hash
+        );
+    }
+
+    #[test]
+    fn test_custom_tag_prefix() {
+        let default_prefix = "-- tag:up\nSELECT 1;\n-- tag:down\nSELECT 2;";
+        let custom_prefix = "-- @migration:up\nSELECT 1;\n-- @migration:down\nSELECT 2;";
+        let configuration = ParserConfiguration::new().tag_prefix("@migration:".to_string());
+
+        let default_file =
+            SQLFile::new_from_string("f", default_prefix, &ParserConfiguration::new()).unwrap();
+        let custom_file = SQLFile::new_from_string("f", custom_prefix, &configuration).unwrap();
+        assert_eq!(custom_file.query_hash_map.len(), 2);
+        for tag in ["up", "down"].iter() {
+            let default_set = default_file.query_hash_map.get(*tag).unwrap();
+            let custom_set = custom_file.query_hash_map.get(*tag).unwrap();
+            assert_eq!(default_set.hash, custom_set.hash);
+        }
+
+        // with a custom prefix the default one is just a comment
+        let maybe_sql_file = SQLFile::new_from_string("f", default_prefix, &configuration);
+        assert!(check_sem(
+            maybe_sql_file,
+            SyntaxErrorMessage::QueryGivenNoTag
+        ));
+    }
+
+    #[test]
+    fn test_case_insensitive_tags() {
+        let queries = "-- tag:Up\nSELECT 1;\n-- tag: DOWN\nSELECT 2;";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        assert!(sql_file.query_hash_map.contains_key("up"));
+        assert!(sql_file.query_hash_map.contains_key("down"));
+        for tag in ["up", "Up", "UP"].iter() {
+            let set = sql_file.get_query_set(tag).unwrap();
+            assert_eq!(&set.queries[0], "SELECT 1;");
+        }
+        assert!(sql_file.get_query_set("Down").is_some());
+    }
+
+    #[test]
+    fn test_duplicate_tag() {
+        let queries = "-- tag:up\nSELECT 1;\n-- tag:down\nSELECT 2;\n-- tag:UP\nSELECT 3;";
+
+        let maybe_sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new());
+        match maybe_sql_file {
+            Err(SQLFileError::SyntaxError(line, err)) => {
+                assert_eq!(line, 5);
+                assert_eq!(
+                    err,
+                    format!("{}", SyntaxErrorMessage::DuplicateTag("up".to_string()))
+                );
+            }
+            _ => panic!("expected a duplicate tag error"),
+        }
+    }
+
+    #[test]
+    fn test_line_delimiter() {
+        let semicolons = "-- tag:up\nCREATE TABLE a (id INT);\nINSERT INTO a VALUES (1);\nINSERT INTO a\nVALUES (2);";
+        let batches = "-- tag:up\nCREATE TABLE a (id INT);\nGO\nINSERT INTO a VALUES (1);\ngo\nINSERT INTO a\nVALUES (2);\nGO\n";
+        let configuration = ParserConfiguration::new().delimiter("GO".to_string());
+
+        let semicolon_file =
+            SQLFile::new_from_string("f", semicolons, &ParserConfiguration::new()).unwrap();
+        let semicolon_set = semicolon_file.query_hash_map.get("up").unwrap();
+        let batch_file = SQLFile::new_from_string("f", batches, &configuration).unwrap();
+        let batch_set = batch_file.query_hash_map.get("up").unwrap();
+        assert_eq!(semicolon_set.queries.len(), 3);
+        assert_eq!(batch_set.queries.len(), 3);
+        assert_eq!(semicolon_set.queries, batch_set.queries);
+    }
+
+    #[test]
+    fn test_line_delimiter_unfinished_batch() {
+        let batches = "-- tag:up\nSELECT 1;\nSELECT 2;";
+        let configuration = ParserConfiguration::new().delimiter("GO".to_string());
+
+        let maybe_sql_file = SQLFile::new_from_string("f", batches, &configuration);
+        assert!(check_sem(
+            maybe_sql_file,
+            SyntaxErrorMessage::EOFIncompleteQuery
+        ));
+    }
+
+    #[test]
+    fn test_tags_keep_file_order() {
+        let queries = "-- tag:up\nSELECT 1;\n-- tag:seed\nSELECT 2;\n-- tag:down\nSELECT 3;\n-- tag:alpha\nSELECT 4;";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        let tags: Vec<&str> = sql_file.query_hash_map.keys().map(|t| t.as_str()).collect();
+        assert_eq!(tags, vec!["up", "seed", "down", "alpha"]);
+        assert_eq!(&sql_file.query_hash_map["down"].queries[0], "SELECT 3;");
+    }
+
+    #[test]
+    fn test_normalized_whitespace_hash() {
+        let original = "-- tag:up\nCREATE TABLE a (id INT, name TEXT);\nSELECT * FROM a;";
+        let reformatted =
+            "-- tag:up\n\nCREATE TABLE a (id   INT,\n\tname TEXT);\n\n   SELECT *\n   FROM a;  ";
+        let configuration = ParserConfiguration::new().normalize_whitespace(true);
+
+        let original_file = SQLFile::new_from_string("f", original, &configuration).unwrap();
+        let reformatted_file = SQLFile::new_from_string("f", reformatted, &configuration).unwrap();
+        assert_eq!(
+            original_file.query_hash_map["up"].hash,
+            reformatted_file.query_hash_map["up"].hash
+        );
+
+        // the queries themselves are kept as written
+        assert_ne!(
+            original_file.query_hash_map["up"].queries,
+            reformatted_file.query_hash_map["up"].queries
+        );
+
+        let original_exact =
+            SQLFile::new_from_string("f", original, &ParserConfiguration::new()).unwrap();
+        let reformatted_exact =
+            SQLFile::new_from_string("f", reformatted, &ParserConfiguration::new()).unwrap();
+        assert_ne!(
+            original_exact.query_hash_map["up"].hash,
+            reformatted_exact.query_hash_map["up"].hash
+        );
+    }
+
+    #[test]
+    fn test_normalized_whitespace_hash_still_sees_changes() {
+        let original = "-- tag:up\nSELECT * FROM a;";
+        let changed = "-- tag:up\nSELECT * FROM b;";
+        let configuration = ParserConfiguration::new().normalize_whitespace(true);
+
+        let original_file = SQLFile::new_from_string("f", original, &configuration).unwrap();
+        let changed_file = SQLFile::new_from_string("f", changed, &configuration).unwrap();
+        assert_ne!(
+            original_file.query_hash_map["up"].hash,
+            changed_file.query_hash_map["up"].hash
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let queries = "-- tag:up\nCREATE TABLE a (\n    id INT\n);\n\nINSERT INTO a VALUES (1); INSERT INTO a VALUES (2);\n-- tag:down\n-- cleanup\nDROP TABLE a;";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        assert_eq!(
+            sql_file.summary(),
+            vec![
+                TagSummary {
+                    tag: "up".to_string(),
+                    tag_line: 1,
+                    query_count: 3,
+                    query_lines: vec![2, 6, 6],
+                },
+                TagSummary {
+                    tag: "down".to_string(),
+                    tag_line: 7,
+                    query_count: 1,
+                    query_lines: vec![9],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let queries = "-- tag:up\nCREATE TABLE a (id INT);\nINSERT INTO a VALUES (1);\n-- tag:down\nDROP TABLE a;\n-- tag:seed (noop)";
+
+        let sql_file =
+            SQLFile::new_from_string("001_a", queries, &ParserConfiguration::new()).unwrap();
+        let hash = |tag| sql_file.get_query_set(tag).unwrap().hash[..12].to_string();
+        assert_eq!(
+            sql_file.to_string(),
+            format!(
+                "001_a\n  up    2 queries  {}\n  down  1 queries  {}\n  seed  0 queries  {}",
+                hash("up"),
+                hash("down"),
+                hash("seed")
+            )
+        );
+    }
+
+    #[test]
+    fn test_noop_tag() {
+        let queries = "-- tag:up\nINSERT INTO a SELECT * FROM b;\n-- tag:down (noop)\n-- data can't be put back\n";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        let set = sql_file.query_hash_map.get("down").unwrap();
+        assert!(set.queries.is_empty());
+
+        let other_file = SQLFile::new_from_string(
+            "g",
+            "-- tag:up\nSELECT 1;\n-- tag:down (NOOP)",
+            &ParserConfiguration::new(),
+        )
+        .unwrap();
+        assert_eq!(other_file.query_hash_map["down"].hash, set.hash);
+    }
+
+    #[test]
+    fn test_only_comments() {
+        let only_comments = "-- author: chuck\n\n/* nothing\nhere */\n-- yet\n";
+        let maybe_sql_file =
+            SQLFile::new_from_string("f", only_comments, &ParserConfiguration::new());
+        assert!(matches!(
+            maybe_sql_file,
+            Err(SQLFileError::SyntaxError(6, message))
+                if message == SyntaxErrorMessage::NoQueriesFound.to_string()
+        ));
+    }
+
+    #[test]
+    fn test_tag_with_only_comments() {
+        let queries =
+            "-- tag:up\nSELECT 1;\n-- tag:down\n-- nothing to undo\n\n-- tag:seed\nSELECT 2;";
+        let maybe_sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new());
+        assert!(matches!(
+            maybe_sql_file,
+            Err(SQLFileError::SyntaxError(6, message))
+                if message == SyntaxErrorMessage::NoQueryForTag.to_string()
+        ));
+
+        let queries = queries.replace("-- tag:down", "-- tag:down (noop)");
+        let sql_file =
+            SQLFile::new_from_string("f", &queries, &ParserConfiguration::new()).unwrap();
+        assert!(sql_file.get_query_set("down").unwrap().queries.is_empty());
+        assert_eq!(sql_file.get_query_set("seed").unwrap().queries.len(), 1);
+    }
+
+    #[test]
+    fn test_noop_tag_with_query() {
+        let queries = "-- tag:down (noop)\nSELECT 1;\n-- tag:up\nSELECT 2;";
+        let maybe_sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new());
+        assert!(check_sem(
+            maybe_sql_file,
+            SyntaxErrorMessage::QueryForNoopTag
+        ));
+    }
+
+    #[test]
+    fn test_checksum_algorithms() {
+        let queries = "-- tag:up\nCREATE TABLE a (id INT);\nCREATE INDEX a_id ON a (id);";
+        let parse = |algorithm| {
+            let configuration = ParserConfiguration::new().checksum_algorithm(algorithm);
+            SQLFile::new_from_string("f", queries, &configuration)
+                .unwrap()
+                .get_query_set("up")
+                .unwrap()
+                .clone()
+        };
+
+        let sha256 = parse(ChecksumAlgorithm::Sha256);
+        let blake3 = parse(ChecksumAlgorithm::Blake3);
+        assert_eq!(sha256.hash, parse(ChecksumAlgorithm::Sha256).hash);
+        assert_eq!(blake3.hash, parse(ChecksumAlgorithm::Blake3).hash);
+        assert_ne!(sha256.hash, blake3.hash);
+        assert_eq!(sha256.hash.len(), 64);
+        assert!(blake3.hash.starts_with("blake3:"));
+        assert_eq!(
+            ChecksumAlgorithm::of_hash(&sha256.hash),
+            ChecksumAlgorithm::Sha256
+        );
+        assert_eq!(
+            ChecksumAlgorithm::of_hash(&blake3.hash),
+            ChecksumAlgorithm::Blake3
+        );
+
+        // a hash recorded with either algorithm matches the query set parsed with the other
+        assert!(blake3.matches_hash(&sha256.hash));
+        assert!(sha256.matches_hash(&blake3.hash));
+        assert!(!sha256.matches_hash(&ChecksumAlgorithm::Blake3.checksum("SELECT 1;")));
+        assert!(!blake3.matches_hash(&ChecksumAlgorithm::Sha256.checksum("SELECT 1;")));
+
+        assert_eq!("BLAKE3".parse(), Ok(ChecksumAlgorithm::Blake3));
+        assert!("md5".parse::<ChecksumAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn test_no_transaction_tag() {
+        let queries = "-- tag:up (no-transaction)\nCREATE INDEX CONCURRENTLY a_id ON a (id);\n-- tag:down\nDROP INDEX a_id;";
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        assert!(!sql_file.query_hash_map["up"].transaction);
+        assert!(sql_file.query_hash_map["down"].transaction);
+
+        let queries = "-- tag:down (noop, No-Transaction)\n-- tag:up\nSELECT 1;";
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        assert!(!sql_file.query_hash_map["down"].transaction);
+    }
+
+    #[test]
+    fn test_unknown_tag_option() {
+        let queries = "-- tag:up (sometimes)\nSELECT 1;";
+        let maybe_sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new());
+        assert!(check_sem(
+            maybe_sql_file,
+            SyntaxErrorMessage::UnknownTagOption("sometimes".to_string())
+        ));
+    }
+
+    #[test]
+    fn test_tag_aliases() {
+        let queries = "-- tag:up, init\nCREATE TABLE a (id INT);\nINSERT INTO a VALUES (1);\n-- tag:down\nDROP TABLE a;";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        assert_eq!(sql_file.query_hash_map.len(), 3);
+        let up = sql_file.query_hash_map.get("up").unwrap();
+        let init = sql_file.query_hash_map.get("init").unwrap();
+        assert_eq!(up.queries.len(), 2);
+        assert_eq!(up.queries, init.queries);
+        assert_eq!(up.hash, init.hash);
+    }
+
+    #[test]
+    fn test_tag_alias_collision() {
+        let existing = "-- tag:up\nSELECT 1;\n-- tag:init,up\nSELECT 2;";
+        let maybe_sql_file = SQLFile::new_from_string("f", existing, &ParserConfiguration::new());
+        assert!(check_sem(
+            maybe_sql_file,
+            SyntaxErrorMessage::DuplicateTag("up".to_string())
+        ));
+
+        let repeated = "-- tag:up,init,up\nSELECT 1;";
+        let maybe_sql_file = SQLFile::new_from_string("f", repeated, &ParserConfiguration::new());
+        assert!(check_sem(
+            maybe_sql_file,
+            SyntaxErrorMessage::DuplicateTag("up".to_string())
+        ));
+
+        let empty_alias = "-- tag:up,\nSELECT 1;";
+        let maybe_sql_file =
+            SQLFile::new_from_string("f", empty_alias, &ParserConfiguration::new());
+        assert!(check_sem(
+            maybe_sql_file,
+            SyntaxErrorMessage::CouldNotParseTagName
+        ));
+    }
+
+    #[test]
+    fn test_lenient_reports_every_error() {
+        let queries = "SELECT 1;\n-- tag:up\nSELECT 2;\n-- tag:down (sometimes)\nSELECT 3;\n-- tag:up\nSELECT 4;";
+        let configuration = ParserConfiguration::new().lenient(true);
+
+        match SQLFile::new_from_string("f", queries, &configuration) {
+            Err(SQLFileError::SyntaxErrors(errors)) => {
+                let errors: Vec<(i32, String)> = errors
+                    .into_iter()
+                    .map(|err| match err {
+                        SQLFileError::SyntaxError(line, message) => (line, message),
+                        _ => panic!("expected a syntax error"),
+                    })
+                    .collect();
+                assert_eq!(
+                    errors,
+                    vec![
+                        (1, format!("{}", SyntaxErrorMessage::QueryGivenNoTag)),
+                        (
+                            4,
+                            format!(
+                                "{}",
+                                SyntaxErrorMessage::UnknownTagOption("sometimes".to_string())
+                            )
+                        ),
+                        (
+                            6,
+                            format!("{}", SyntaxErrorMessage::DuplicateTag("up".to_string()))
+                        ),
+                    ]
+                );
+            }
+            _ => panic!("expected every syntax error"),
+        }
+
+        // strict parsing stops at the first one
+        let maybe_sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new());
+        assert!(check_sem(
+            maybe_sql_file,
+            SyntaxErrorMessage::QueryGivenNoTag
+        ));
+    }
+
+    #[test]
+    fn test_lenient_valid_file() {
+        let queries = "-- tag:up\nSELECT 1;\n-- tag:down\nSELECT 2;";
+        let configuration = ParserConfiguration::new().lenient(true);
+
+        let sql_file = SQLFile::new_from_string("f", queries, &configuration).unwrap();
+        assert_eq!(sql_file.query_hash_map.len(), 2);
+    }
+
+    #[test]
+    fn test_trigger_function_body() {
+        let function = "-- tag:up
+CREATE OR REPLACE FUNCTION log_user_changes() RETURNS trigger AS $$
+DECLARE
+    note TEXT := 'changed; see audit';
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        INSERT INTO user_audit (user_id, note) VALUES (OLD.id, 'deleted; ' || OLD.email);
+        RETURN OLD;
+    END IF;
+    BEGIN
+        INSERT INTO user_audit (user_id, note) VALUES (NEW.id, note);
+    EXCEPTION WHEN unique_violation THEN
+        RAISE NOTICE 'duplicate audit row for %; skipping', NEW.id;
+    END;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;";
+
+        let sql_file =
+            SQLFile::new_from_string("f", function, &ParserConfiguration::new()).unwrap();
+        let set = sql_file.query_hash_map.get("up").unwrap();
+        assert_eq!(set.queries.len(), 1);
+        assert_eq!(&set.queries[0], &function["-- tag:up\n".len()..]);
+    }
+
+    #[test]
+    fn test_quoted_function_body() {
+        let function = "-- tag:up
+CREATE FUNCTION touch() RETURNS trigger AS '
+BEGIN
+    NEW.updated_at := now();
+    RAISE NOTICE ''touched; ok'';
+    RETURN NEW;
+END;
+' LANGUAGE plpgsql;
+CREATE TRIGGER touch BEFORE UPDATE ON users FOR EACH ROW EXECUTE PROCEDURE touch();";
+
+        let sql_file =
+            SQLFile::new_from_string("f", function, &ParserConfiguration::new()).unwrap();
+        let set = sql_file.query_hash_map.get("up").unwrap();
+        assert_eq!(set.queries.len(), 2);
+        assert!(set.queries[0].ends_with("' LANGUAGE plpgsql;"));
+        assert!(set.queries[1].starts_with("CREATE TRIGGER"));
+    }
+
+    #[test]
+    fn test_transaction_statements() {
+        let queries = "-- tag:up\nBEGIN;\nUPDATE a SET b = 1;\nEND;";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        assert_eq!(sql_file.query_hash_map["up"].queries.len(), 3);
+    }
+
+    #[test]
+    fn test_copy_from_stdin() {
+        let copy = "-- tag:up\nCREATE TABLE notes (id INT, body TEXT);\ncopy notes (id, body) from stdin;\n1\tfirst; note\n2\t-- not a comment\n3\t-- tag:down\n\\.\nSELECT count(*) FROM notes;";
+
+        let sql_file = SQLFile::new_from_string("f", copy, &ParserConfiguration::new()).unwrap();
+        assert_eq!(sql_file.query_hash_map.len(), 1);
+        let set = sql_file.query_hash_map.get("up").unwrap();
+        assert_eq!(set.queries.len(), 3);
+        assert_eq!(
+            &set.queries[1],
+            "copy notes (id, body) from stdin;\n1\tfirst; note\n2\t-- not a comment\n3\t-- tag:down\n\\."
+        );
+        assert_eq!(&set.queries[2], "SELECT count(*) FROM notes;");
+    }
+
+    #[test]
+    fn test_copy_without_terminator() {
+        let copy = "-- tag:up\nCOPY notes FROM STDIN;\n1\tfirst\n";
+        let maybe_sql_file = SQLFile::new_from_string("f", copy, &ParserConfiguration::new());
+        assert!(check_sem(
+            maybe_sql_file,
+            SyntaxErrorMessage::EOFIncompleteQuery
+        ));
+    }
+
+    #[test]
+    fn test_copy_to_stdout() {
+        let copy = "-- tag:up\nCOPY notes TO STDOUT;\nSELECT 1;";
+
+        let sql_file = SQLFile::new_from_string("f", copy, &ParserConfiguration::new()).unwrap();
+        assert_eq!(sql_file.query_hash_map["up"].queries.len(), 2);
+    }
+
+    #[test]
+    fn test_split_copy_data() {
+        let copy = "-- tag:up\nCOPY notes (id, body)\n  FROM STDIN WITH (DELIMITER ';');\n1;first\n2;-- second\n\\.";
+
+        let sql_file = SQLFile::new_from_string("f", copy, &ParserConfiguration::new()).unwrap();
+        let query = &sql_file.query_hash_map["up"].queries[0];
+        assert_eq!(
+            split_copy_data(query),
+            Some((
+                "COPY notes (id, body)\n  FROM STDIN WITH (DELIMITER ';');",
+                "1;first\n2;-- second\n"
+            ))
+        );
+        assert_eq!(
+            split_copy_data("COPY notes FROM STDIN;\n\\."),
+            Some(("COPY notes FROM STDIN;", ""))
+        );
+        assert_eq!(split_copy_data("COPY notes TO STDOUT;"), None);
+        assert_eq!(split_copy_data("SELECT 1;"), None);
+    }
+
+    #[test]
+    fn test_multibyte_tag_line() {
+        let queries = "-- ✓ données tag:up\nSELECT 1;\n-- tag:bajá\nSELECT 2;";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        assert!(sql_file.query_hash_map.contains_key("up"));
+        assert!(sql_file.query_hash_map.contains_key("bajá"));
+    }
+
+    #[test]
+    fn test_tag_declaration_line() {
+        let queries = "-- migration for the users table\n\n-- tag:up,init\nCREATE TABLE users (id INT);\n-- tag:down\nDROP TABLE users;";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        assert_eq!(sql_file.query_hash_map["up"].tag_line, 3);
+        assert_eq!(sql_file.query_hash_map["init"].tag_line, 3);
+        assert_eq!(sql_file.query_hash_map["down"].tag_line, 5);
+    }
+
+    #[test]
+    fn test_valid_tag_names() {
+        let queries = "-- tag:up_2024-01\nSELECT 1;\n-- tag:Seed_Data\nSELECT 2;";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        assert!(sql_file.query_hash_map.contains_key("up_2024-01"));
+        assert!(sql_file.query_hash_map.contains_key("seed_data"));
+    }
+
+    #[test]
+    fn test_invalid_tag_names() {
+        let with_space = "-- tag:seed data\nSELECT 1;";
+        let maybe_sql_file = SQLFile::new_from_string("f", with_space, &ParserConfiguration::new());
+        assert!(check_sem(
+            maybe_sql_file,
+            SyntaxErrorMessage::InvalidTagName("seed data".to_string())
+        ));
+
+        let with_quote = "-- tag:up,x'; DROP TABLE users; --\nSELECT 1;";
+        let maybe_sql_file = SQLFile::new_from_string("f", with_quote, &ParserConfiguration::new());
+        assert!(check_sem(
+            maybe_sql_file,
+            SyntaxErrorMessage::InvalidTagName("x'; drop table users; --".to_string())
+        ));
+    }
+
+    #[test]
+    fn test_metadata_header() {
+        let queries = "-- Description: add users table\n-- author: alice\n-- just a comment\n-- tag:up\n-- note: not metadata\nCREATE TABLE users (id INT);";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        assert_eq!(sql_file.metadata.len(), 2);
+        assert_eq!(sql_file.metadata["description"], "add users table");
+        assert_eq!(sql_file.metadata["author"], "alice");
+        assert_eq!(sql_file.query_hash_map["up"].queries.len(), 1);
+    }
+
+    #[test]
+    fn test_metadata_header_ignores_other_comments() {
+        let queries = "-- TODO: fix the index\n-- NOTE: runs long\n-- tag:up\n-- author: bob\nCREATE TABLE users (id INT);";
+
+        let sql_file = SQLFile::new_from_string("f", queries, &ParserConfiguration::new()).unwrap();
+        assert!(sql_file.metadata.is_empty());
+    }
+
+    #[test]
+    fn test_version() {
+        let version = |name: &str| {
+            SQLFile::new_from_string(name, "-- tag:up\nSELECT 1;", &ParserConfiguration::new())
+                .unwrap()
+                .version()
+        };
+        assert_eq!(version("001_init"), Some(1));
+        assert_eq!(version("42"), Some(42));
+        assert_eq!(version("V20240115__add_index"), Some(20240115));
+        assert_eq!(version("v2_users"), Some(2));
+        assert_eq!(version("init"), None);
+        assert_eq!(version("Vacuum"), None);
+        assert_eq!(version("_001"), None);
+    }
+
+    #[test]
+    fn test_file_stem() {
+        let configuration = ParserConfiguration::new();
+        let stem = |path: &str| SQLFile::file_stem(path, &configuration);
+        assert_eq!(stem("migrations/001.up.sql"), Some("001.up".to_string()));
+        assert_eq!(stem("001_init.SQL"), Some("001_init".to_string()));
+        assert_eq!(stem("001_init.sql.j2"), Some("001_init.sql".to_string()));
+        assert_eq!(stem("notes.txt"), Some("notes".to_string()));
+        assert_eq!(stem(".sql"), None);
+
+        let configuration = ParserConfiguration::new().template_suffix(".j2".to_string());
+        let stem = |path: &str| SQLFile::file_stem(path, &configuration);
+        assert_eq!(
+            stem("migrations/001_init.sql.j2"),
+            Some("001_init".to_string())
+        );
+        assert_eq!(stem("001.up.sql"), Some("001.up".to_string()));
+    }
+
+    #[test]
+    fn test_new_from_reader() {
+        let configuration = ParserConfiguration::new();
+        let texts = [
+            "\u{FEFF}-- author: ana\r\n-- tag:up\r\nCREATE TABLE a (\r\n  id INT\r\n);\r\n-- tag:down\r\nDROP TABLE a;\r\n",
+            "-- tag:up\nSELECT 'a\n-- tag:down\nb';\nSELECT 2;",
+        ];
+        for text in texts.iter() {
+            let reader = std::io::Cursor::new(text.as_bytes());
+            let streamed = SQLFile::new_from_reader("a", reader, &configuration).unwrap();
+            let whole = SQLFile::new_from_string("a", text, &configuration).unwrap();
+            assert_eq!(streamed.summary(), whole.summary());
+            assert_eq!(streamed.metadata, whole.metadata);
+            for summary in whole.summary() {
+                assert_eq!(
+                    streamed.get_query_set(&summary.tag).unwrap().queries,
+                    whole.get_query_set(&summary.tag).unwrap().queries
+                );
+                assert_eq!(
+                    streamed.get_query_set(&summary.tag).unwrap().hash,
+                    whole.get_query_set(&summary.tag).unwrap().hash
+                );
+            }
+        }
+
+        // the line an error is reported on counts the empty line after a final newline
+        let reader = std::io::Cursor::new("-- tag:up\nSELECT 1\n");
+        let err = SQLFile::new_from_reader("a", reader, &configuration).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            SyntaxErrorMessage::EOFIncompleteQuery
+                .to_error(3)
+                .to_string()
+        );
+
+        let reader = std::io::Cursor::new(vec![b'-', b'-', 0xff, b'\n']);
+        assert!(matches!(
+            SQLFile::new_from_reader("a", reader, &configuration),
+            Err(SQLFileError::CouldNoReadFile)
+        ));
+    }
+
+    #[test]
+    fn test_new_from_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<_> = (0..200)
+            .map(|i| {
+                let path = dir.path().join(format!("{:03}_file.sql", i));
+                let contents = if i == 150 {
+                    "SELECT 1;".to_string()
+                } else {
+                    format!("-- tag:up\nSELECT {};\n-- tag:down\nSELECT -{};", i, i)
+                };
+                std::fs::write(&path, contents).unwrap();
+                path
+            })
+            .collect();
+
+        let configuration = ParserConfiguration::new();
+        let results = SQLFile::new_from_files(&paths, &configuration);
+        assert_eq!(results.len(), paths.len());
+        for (i, (path, result)) in paths.iter().zip(results).enumerate() {
+            if i == 150 {
+                assert!(result.is_err());
+                continue;
+            }
+            let file = result.unwrap();
+            assert_eq!(file.name, format!("{:03}_file", i));
+            let serial = SQLFile::new_from_file(path, &configuration).unwrap();
+            assert_eq!(file.summary(), serial.summary());
+            assert_eq!(
+                file.get_query_set("up").unwrap().hash,
+                serial.get_query_set("up").unwrap().hash
+            );
+        }
+    }
 }
@@ -0,0 +1,146 @@
+use crate::database::{DatabaseClient, Dialect, Row, Value};
+use crate::error::OspreyError;
+use crate::sql_file::split_copy_data;
+use rusqlite::types::{ToSqlOutput, ValueRef};
+use rusqlite::{params_from_iter, Connection, ToSql};
+
+// SqliteClient runs migrations against a sqlite database file
+pub struct SqliteClient {
+    connection: Connection,
+}
+
+impl SqliteClient {
+    pub fn open(path: &str) -> Result<SqliteClient, OspreyError> {
+        let connection = Connection::open(path)?;
+        Ok(SqliteClient { connection })
+    }
+
+    pub fn open_in_memory() -> Result<SqliteClient, OspreyError> {
+        let connection = Connection::open_in_memory()?;
+        Ok(SqliteClient { connection })
+    }
+}
+
+impl From<rusqlite::Error> for OspreyError {
+    fn from(err: rusqlite::Error) -> Self {
+        OspreyError::Database(Box::new(err))
+    }
+}
+
+impl ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        match self {
+            Value::Null => Ok(ToSqlOutput::Borrowed(ValueRef::Null)),
+            Value::Integer(i) => Ok(ToSqlOutput::from(*i)),
+            Value::Text(s) => Ok(ToSqlOutput::from(s.as_str())),
+        }
+    }
+}
+
+impl DatabaseClient for SqliteClient {
+    fn dialect(&self) -> Dialect {
+        Dialect::Sqlite
+    }
+
+    fn batch_execute(&mut self, query: &str) -> Result<(), OspreyError> {
+        self.connection.execute_batch(query)?;
+        Ok(())
+    }
+
+    // a batch only reports the changes of its last statement, the connection's running total
+    // covers all of them
+    fn execute(&mut self, query: &str) -> Result<u64, OspreyError> {
+        if split_copy_data(query).is_some() {
+            return Err(OspreyError::CopyNotSupported);
+        }
+        let total_changes = |connection: &Connection| {
+            connection.query_row("SELECT total_changes()", [], |row| row.get::<_, i64>(0))
+        };
+        let before = total_changes(&self.connection)?;
+        self.connection.execute_batch(query)?;
+        Ok((total_changes(&self.connection)? - before) as u64)
+    }
+
+    fn execute_params(&mut self, query: &str, params: &[Value]) -> Result<(), OspreyError> {
+        self.connection.execute(query, params_from_iter(params))?;
+        Ok(())
+    }
+
+    fn query_row(&mut self, query: &str, params: &[Value]) -> Result<Vec<Row>, OspreyError> {
+        let mut statement = self.connection.prepare(query)?;
+        let column_count = statement.column_count();
+
+        let rows = statement.query_map(params_from_iter(params), |row| {
+            (0..column_count)
+                .map(|i| {
+                    // sqlite is loosely typed, anything that isn't an integer is read as text
+                    Ok(match row.get_ref(i)? {
+                        ValueRef::Null => Value::Null,
+                        ValueRef::Integer(i) => Value::Integer(i),
+                        ValueRef::Real(f) => Value::Text(f.to_string()),
+                        ValueRef::Text(t) | ValueRef::Blob(t) => {
+                            Value::Text(String::from_utf8_lossy(t).into_owned())
+                        }
+                    })
+                })
+                .collect::<rusqlite::Result<Row>>()
+        })?;
+
+        Ok(rows.collect::<rusqlite::Result<Vec<Row>>>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_execute_affected_rows() {
+        let mut client = SqliteClient::open_in_memory().unwrap();
+        assert_eq!(client.execute("CREATE TABLE t (id INTEGER);").unwrap(), 0);
+        assert_eq!(
+            client
+                .execute("INSERT INTO t VALUES (1), (2); INSERT INTO t VALUES (3);")
+                .unwrap(),
+            3
+        );
+        assert_eq!(client.execute("DELETE FROM t WHERE id > 1;").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_execute_copy() {
+        let mut client = SqliteClient::open_in_memory().unwrap();
+        client
+            .batch_execute("CREATE TABLE t (id INTEGER);")
+            .unwrap();
+        assert!(matches!(
+            client.execute("COPY t (id) FROM STDIN;\n1\n\\."),
+            Err(OspreyError::CopyNotSupported)
+        ));
+    }
+
+    #[test]
+    fn test_query_row_values() {
+        let mut client = SqliteClient::open_in_memory().unwrap();
+        client
+            .batch_execute("CREATE TABLE t (id INTEGER, name TEXT, score REAL);")
+            .unwrap();
+        client
+            .execute_params(
+                "INSERT INTO t VALUES ($1, $2, $3);",
+                &[Value::from(7), Value::from("seven"), Value::Null],
+            )
+            .unwrap();
+
+        let rows = client
+            .query_row(
+                "SELECT id, name, score FROM t WHERE name = $1",
+                &["seven".into()],
+            )
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![vec![Value::Integer(7), Value::from("seven"), Value::Null]]
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use crate::sql_file::SQLFile;
+use std::fmt;
+
+// versions at or above this are taken to be timestamps, which aren't expected to be
+// consecutive, so gaps are only reported for versions below it
+const SEQUENCE_VERSION_LIMIT: u64 = 1_000_000;
+
+// VersionIssue is a problem with the version numbers migration files start with
+//   Gap - no file has a version between the two versions
+//   Duplicate - two files share a version
+#[derive(Debug, PartialEq)]
+pub enum VersionIssue {
+    Gap(u64, u64),
+    Duplicate(u64, String, String),
+}
+
+impl fmt::Display for VersionIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VersionIssue::Gap(before, after) => {
+                write!(f, "Versions between {} and {} are missing", before, after)
+            }
+            VersionIssue::Duplicate(version, first, second) => write!(
+                f,
+                "The files {} and {} both have version {}",
+                first, second, version
+            ),
+        }
+    }
+}
+
+// checks the versions of the files that have one for gaps and duplicates, files without a
+// version are ignored
+pub fn check_versions(sql_sets: &[SQLFile]) -> Vec<VersionIssue> {
+    let mut versioned: Vec<(u64, &str)> = sql_sets
+        .iter()
+        .filter_map(|file| Some((file.version()?, file.name.as_str())))
+        .collect();
+    versioned.sort_unstable();
+
+    let check_gaps = versioned
+        .iter()
+        .all(|(version, _)| *version < SEQUENCE_VERSION_LIMIT);
+
+    let mut issues = vec![];
+    for pair in versioned.windows(2) {
+        let (previous, previous_name) = pair[0];
+        let (version, name) = pair[1];
+        if version == previous {
+            issues.push(VersionIssue::Duplicate(
+                version,
+                previous_name.to_string(),
+                name.to_string(),
+            ));
+        } else if check_gaps && version > previous + 1 {
+            issues.push(VersionIssue::Gap(previous, version));
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_file::ParserConfiguration;
+
+    fn files(names: &[&str]) -> Vec<SQLFile> {
+        names
+            .iter()
+            .map(|name| {
+                SQLFile::new_from_string(name, "-- tag:up\nSELECT 1;", &ParserConfiguration::new())
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_consecutive_versions() {
+        assert!(check_versions(&files(&["002_b", "001_a", "003_c", "notes"])).is_empty());
+    }
+
+    #[test]
+    fn test_gap() {
+        assert_eq!(
+            check_versions(&files(&["001_a", "003_c"])),
+            vec![VersionIssue::Gap(1, 3)]
+        );
+    }
+
+    #[test]
+    fn test_duplicate() {
+        assert_eq!(
+            check_versions(&files(&["001_a", "002_b", "002_c"])),
+            vec![VersionIssue::Duplicate(
+                2,
+                "002_b".to_string(),
+                "002_c".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_timestamp_versions_have_no_gaps() {
+        let sql_sets = files(&["V20240115__a", "V20240120__b", "V20240120__c"]);
+        assert_eq!(
+            check_versions(&sql_sets),
+            vec![VersionIssue::Duplicate(
+                20240120,
+                "V20240120__b".to_string(),
+                "V20240120__c".to_string()
+            )]
+        );
+    }
+}